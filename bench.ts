@@ -0,0 +1,51 @@
+import buildWithRuntime from "./mod_runtime_agnostic.ts";
+
+const encoder = new TextEncoder();
+const password = encoder.encode("here's a very cool password");
+const salt = encoder.encode("xenon2's so cool");
+
+const scalar = await buildWithRuntime(WebAssembly, { simd: false });
+const simd = await buildWithRuntime(WebAssembly, { simd: true });
+// Only benched once built with `deno task build:scratch`
+const scratchSource = await import("./wasm/wasm_scratch.js")
+  .then(({ source }) => source(WebAssembly))
+  .catch(() => undefined);
+const scratch = scratchSource &&
+  await buildWithRuntime(WebAssembly, { module: await WebAssembly.compile(scratchSource) });
+
+// OWASP defaults: m=19456, t=2, p=1
+Deno.bench({
+  name: "hash scalar",
+  group: "hash",
+  baseline: true,
+  fn: () => {
+    scalar.hash(password, salt);
+  },
+});
+
+Deno.bench({
+  name: "hash SIMD",
+  group: "hash",
+  fn: () => {
+    simd.hash(password, salt);
+  },
+});
+
+// The scratch build is scalar, so it's compared against the scalar linear build
+Deno.bench({
+  name: "hash linear",
+  group: "memory",
+  baseline: true,
+  fn: () => {
+    scalar.hash(password, salt);
+  },
+});
+
+Deno.bench({
+  name: "hash scratch",
+  group: "memory",
+  ignore: !scratch,
+  fn: () => {
+    scratch!.hash(password, salt);
+  },
+});
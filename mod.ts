@@ -1,8 +1,34 @@
 import buildWithRuntime, { type Argon2Runtime } from "./mod_runtime_agnostic.ts";
 export type * from "./mod_runtime_agnostic.ts";
+export { Argon2Error, Argon2ErrorCode } from "./mod_runtime_agnostic.ts";
 
 const nativeRuntime: Argon2Runtime = await buildWithRuntime(WebAssembly);
 const hash = nativeRuntime.hash;
+const hashRaw = nativeRuntime.hashRaw;
 const verify = nativeRuntime.verify;
+const needsRehash = nativeRuntime.needsRehash;
+const verifyAndUpgrade = nativeRuntime.verifyAndUpgrade;
+const setVerifyPolicy = nativeRuntime.setVerifyPolicy;
+const createContext = nativeRuntime.createContext;
+const beginHash = nativeRuntime.beginHash;
+const memoryStats = nativeRuntime.memoryStats;
+const setMemoryLimit = nativeRuntime.setMemoryLimit;
+const isPoisoned = nativeRuntime.isPoisoned;
+const reset = nativeRuntime.reset;
+const capabilities = nativeRuntime.capabilities;
 
-export { hash, verify };
+export {
+  beginHash,
+  capabilities,
+  createContext,
+  hash,
+  hashRaw,
+  isPoisoned,
+  memoryStats,
+  needsRehash,
+  reset,
+  setMemoryLimit,
+  setVerifyPolicy,
+  verify,
+  verifyAndUpgrade,
+};
@@ -0,0 +1,34 @@
+import buildWithRuntime, { type Argon2Runtime } from "./mod_runtime_agnostic.ts";
+export type * from "./mod_runtime_agnostic.ts";
+export { Argon2Error, Argon2ErrorCode } from "./mod_runtime_agnostic.ts";
+import { source } from "./wasm/wasm_atomics.js";
+import { createSharedMemory } from "./wasm/thread.ts";
+
+/**
+ * The `atomics` build together with the memory its instances share. It can be
+ * posted to workers, each of which passes it to {@link instantiate}.
+ */
+export type SharedModule = {
+  module: WebAssembly.Module;
+  memory: WebAssembly.Memory;
+};
+
+/**
+ * Compiles the `atomics` build and creates the shared memory for it.
+ */
+export async function compile(): Promise<SharedModule> {
+  return {
+    module: await WebAssembly.compile(await source(WebAssembly)),
+    memory: createSharedMemory(),
+  };
+}
+
+/**
+ * Instantiates a shared module for the calling thread, which may then hash and
+ * verify concurrently with the other threads sharing its memory. Contexts,
+ * hash jobs, the verify policy and error messages belong to the instance that
+ * created or set them; the memory limit applies to the whole shared memory.
+ */
+export function instantiate(shared: SharedModule): Promise<Argon2Runtime> {
+  return buildWithRuntime(WebAssembly, shared);
+}
@@ -0,0 +1,39 @@
+import buildWithRuntime, { type Argon2Runtime } from "./mod_runtime_agnostic.ts";
+export type * from "./mod_runtime_agnostic.ts";
+export { Argon2Error, Argon2ErrorCode } from "./mod_runtime_agnostic.ts";
+import { source } from "./wasm/wasm_memory64.js";
+
+// The memory64 build addresses its memory with 64-bit pointers, so `mCost`
+// may exceed 4 GiB. It needs a runtime supporting the memory64 proposal.
+const memory64Runtime: Argon2Runtime = await buildWithRuntime(WebAssembly, {
+  module: await WebAssembly.compile(await source(WebAssembly)),
+});
+const hash = memory64Runtime.hash;
+const hashRaw = memory64Runtime.hashRaw;
+const verify = memory64Runtime.verify;
+const needsRehash = memory64Runtime.needsRehash;
+const verifyAndUpgrade = memory64Runtime.verifyAndUpgrade;
+const setVerifyPolicy = memory64Runtime.setVerifyPolicy;
+const createContext = memory64Runtime.createContext;
+const beginHash = memory64Runtime.beginHash;
+const memoryStats = memory64Runtime.memoryStats;
+const setMemoryLimit = memory64Runtime.setMemoryLimit;
+const isPoisoned = memory64Runtime.isPoisoned;
+const reset = memory64Runtime.reset;
+const capabilities = memory64Runtime.capabilities;
+
+export {
+  beginHash,
+  capabilities,
+  createContext,
+  hash,
+  hashRaw,
+  isPoisoned,
+  memoryStats,
+  needsRehash,
+  reset,
+  setMemoryLimit,
+  setVerifyPolicy,
+  verify,
+  verifyAndUpgrade,
+};
@@ -1,9 +1,36 @@
 import buildWithRuntime, { type Argon2Runtime } from "./mod_runtime_agnostic.ts";
 export type * from "./mod_runtime_agnostic.ts";
+export { Argon2Error, Argon2ErrorCode } from "./mod_runtime_agnostic.ts";
 import { WebAssembly } from "@blckbrry/polywasm";
 
-const polyfillRuntime: Argon2Runtime = await buildWithRuntime(WebAssembly as unknown as typeof globalThis.WebAssembly);
+// The polyfill only implements scalar instructions
+const polyfillRuntime: Argon2Runtime = await buildWithRuntime(WebAssembly as unknown as typeof globalThis.WebAssembly, { simd: false });
 const hash = polyfillRuntime.hash;
+const hashRaw = polyfillRuntime.hashRaw;
 const verify = polyfillRuntime.verify;
+const needsRehash = polyfillRuntime.needsRehash;
+const verifyAndUpgrade = polyfillRuntime.verifyAndUpgrade;
+const setVerifyPolicy = polyfillRuntime.setVerifyPolicy;
+const createContext = polyfillRuntime.createContext;
+const beginHash = polyfillRuntime.beginHash;
+const memoryStats = polyfillRuntime.memoryStats;
+const setMemoryLimit = polyfillRuntime.setMemoryLimit;
+const isPoisoned = polyfillRuntime.isPoisoned;
+const reset = polyfillRuntime.reset;
+const capabilities = polyfillRuntime.capabilities;
 
-export { hash, verify };
+export {
+  beginHash,
+  capabilities,
+  createContext,
+  hash,
+  hashRaw,
+  isPoisoned,
+  memoryStats,
+  needsRehash,
+  reset,
+  setMemoryLimit,
+  setVerifyPolicy,
+  verify,
+  verifyAndUpgrade,
+};
@@ -1,4 +1,4 @@
-import wasmBuilder from "./wasm/mod.ts";
+import wasmBuilder, { type WasmOptions } from "./wasm/mod.ts";
 // import * as wasm from "./wasm/mod.ts";
 
 /**
@@ -22,6 +22,11 @@ export type Argon2Params = {
   algorithm: Argon2Algorithm;
   version: Argon2Version;
   secret?: ArrayBufferLike;
+  /**
+   * Associated data bound to the hash, up to 32 bytes. Appears as `data=` in
+   * the PHC digest and is read back from it by {@link verify}.
+   */
+  data?: BufferSource;
   /**
    * Memory size in 1 KiB blocks. Between 1 and (2^32)-1.
    *
@@ -44,15 +49,255 @@ export type Argon2Params = {
    * @default 1
    */
   pCost?: number;
+  /**
+   * Length of the hash (tag) in bytes. Between 4 and 2^32 - 1, as reported by
+   * {@link capabilities}, for raw hashes and PHC digests alike.
+   *
+   * {@link verify} always uses the tag length encoded in the digest.
+   *
+   * @default 32
+   */
+  outputLen?: number;
+  /**
+   * Polled between slices of the computation (four times per iteration).
+   * Returning `true` abandons it with {@link Argon2ErrorCode.Cancelled}, for
+   * example once a deadline has passed. Anything it throws is rethrown after
+   * the computation stops.
+   */
+  shouldCancel?: () => boolean;
+  /**
+   * Called each time a segment of memory (one lane of one slice) has been
+   * computed. Anything it throws cancels the computation and is rethrown.
+   */
+  onProgress?: (progress: Argon2Progress) => void;
 };
 
+/**
+ * Where an Argon2 computation is, as reported to {@link Argon2Params.onProgress}.
+ */
+export type Argon2Progress = {
+  /** Iteration of the segment just computed, from 0 to `tCost - 1`. */
+  pass: number;
+  /** Slice of the segment just computed, from 0 to 3. */
+  slice: number;
+  /** Lane of the segment just computed, from 0 to `pCost - 1`. */
+  lane: number;
+  /** Fraction of the computation done, from 0 to 1. */
+  fraction: number;
+};
+
+/**
+ * Options for {@link verify}.
+ */
+export type VerifyOptions = Pick<Argon2Params, "shouldCancel" | "onProgress">;
+
+/**
+ * Limits applied to digests before {@link verify} computes them, so digests from
+ * less trusted sources can't make verification allocate or run arbitrarily much.
+ * Digests outside the policy fail with {@link Argon2ErrorCode.PolicyViolation}.
+ *
+ * Omitted fields are unrestricted.
+ */
+export type VerifyPolicy = {
+  /** Maximum memory size in 1 KiB blocks. */
+  maxMCost?: number;
+  /** Maximum number of iterations. */
+  maxTCost?: number;
+  /** Maximum degree of parallelism. */
+  maxPCost?: number;
+  /** Algorithms digests may use. */
+  algorithms?: Argon2Algorithm[];
+  /** Versions digests may use. */
+  versions?: Argon2Version[];
+};
+
+/**
+ * Status codes reported by the wasm module when a call fails.
+ *
+ * These mirror the `Error` enum in `wasm/error.rs`.
+ */
+export enum Argon2ErrorCode {
+  InvalidAlgorithm = 1,
+  InvalidVersion = 2,
+  InvalidParams = 3,
+  SaltTooShort = 4,
+  MalformedDigest = 5,
+  OutOfMemory = 6,
+  InvalidInput = 7,
+  PolicyViolation = 8,
+  InvalidHandle = 9,
+  Cancelled = 10,
+  Unfinished = 11,
+}
+
+/**
+ * Thrown when the wasm module rejects a call. The module stays usable afterwards.
+ */
+export class Argon2Error extends Error {
+  constructor(readonly code: Argon2ErrorCode, message?: string) {
+    super(message || (Argon2ErrorCode[code] ?? `Unknown error ${code}`));
+    this.name = "Argon2Error";
+  }
+}
+
+type ResolvedParams = Argon2Params & Required<Pick<Argon2Params, "mCost" | "tCost" | "pCost" | "outputLen">>;
+
 export type HashFunctionType = (password: BufferSource, salt: BufferSource, params?: Argon2Params) => string;
-export type VerifyFunctionType = (digest: string, password: BufferSource, secret?: BufferSource) => boolean;
+export type HashRawFunctionType = (password: BufferSource, salt: BufferSource, length: number, params?: Argon2Params) => Uint8Array;
+export type VerifyFunctionType = (
+  digest: string,
+  password: BufferSource,
+  secret?: BufferSource,
+  options?: VerifyOptions,
+) => boolean;
+export type NeedsRehashFunctionType = (digest: string, params?: Argon2Params, saltLen?: number) => boolean;
+export type VerifyAndUpgradeFunctionType = (
+  digest: string,
+  password: BufferSource,
+  salt: BufferSource,
+  params?: Argon2Params,
+) => { matches: boolean; digest?: string };
 
-export type Argon2Runtime = { hash: HashFunctionType, verify: VerifyFunctionType };
+export type SetVerifyPolicyFunctionType = (policy: VerifyPolicy) => void;
 
-export default async (_WebAssembly: typeof WebAssembly): Promise<Argon2Runtime> => {
-  const wasm = await wasmBuilder(_WebAssembly);
+/**
+ * A hasher bound to one set of parameters that keeps its Argon2 memory between
+ * calls instead of reallocating it. Call {@link Argon2Context.free} when done.
+ */
+export type Argon2Context = {
+  hash(password: BufferSource, salt: BufferSource): string;
+  verify(digest: string, password: BufferSource): boolean;
+  free(): void;
+};
+export type CreateContextFunctionType = (params?: Argon2Params) => Argon2Context;
+
+/**
+ * A hash computed a bounded amount at a time, for example between frames or
+ * other work. Call {@link Argon2HashJob.step} until it returns `true`, then
+ * {@link Argon2HashJob.finish} or {@link Argon2HashJob.finishRaw}, which free
+ * the job. Call {@link Argon2HashJob.abort} to give up on it early.
+ */
+export type Argon2HashJob = {
+  /**
+   * Computes at most `budget` more 1 KiB memory blocks, returning whether all
+   * of them have been computed. Without a budget the job runs to completion.
+   */
+  step(budget?: number): boolean;
+  /** Returns the PHC digest, as {@link hash} would have. */
+  finish(): string;
+  /** Returns the raw tag of `outputLen` bytes, as {@link hashRaw} would have. */
+  finishRaw(): Uint8Array;
+  abort(): void;
+};
+export type BeginHashFunctionType = (password: BufferSource, salt: BufferSource, params?: Argon2Params) => Argon2HashJob;
+
+/**
+ * Heap usage of the wasm module, as counted by its allocator.
+ */
+export type MemoryStats = {
+  /** Bytes currently allocated. */
+  allocatedBytes: number;
+  /** Highest number of bytes allocated at once since instantiation. */
+  peakBytes: number;
+  /** Allocations not yet freed. */
+  liveAllocations: number;
+  /** Size of linear memory in 64 KiB wasm pages. Linear memory never shrinks. */
+  pages: number;
+};
+export type MemoryStatsFunctionType = () => MemoryStats;
+export type SetMemoryLimitFunctionType = (maxBytes?: number) => void;
+export type IsPoisonedFunctionType = () => boolean;
+export type ResetFunctionType = () => void;
+export type TerminateFunctionType = () => void;
+
+/**
+ * What the loaded build of the wasm module supports, as reported by it.
+ */
+export type Argon2Capabilities = {
+  /** Version of the module's exports, the one these bindings are written for. */
+  abiVersion: number;
+  algorithms: Argon2Algorithm[];
+  versions: Argon2Version[];
+  /** Optional features, which vary between builds. */
+  features: {
+    /** Hashes may be keyed with `secret`. */
+    secret: boolean;
+    /** Hashes may be bound to `data`. */
+    associatedData: boolean;
+    /** {@link hashRaw} and {@link Argon2HashJob.finishRaw} are available. */
+    rawOutput: boolean;
+    /** Lanes are computed on worker threads when `pCost` is above 1. */
+    threads: boolean;
+    /** Hashing uses wasm SIMD instructions. */
+    simd: boolean;
+    /** Instances on several threads may share the module's memory. */
+    atomics: boolean;
+    /** The module's memory is addressed with 64-bit pointers. */
+    memory64: boolean;
+    /** Argon2 memory is kept outside the module's memory. */
+    scratch: boolean;
+  };
+  /** Inclusive limits of parameters, with `mCost` in 1 KiB blocks, and of input lengths in bytes. */
+  limits: {
+    minMCost: number;
+    maxMCost: number;
+    minTCost: number;
+    maxTCost: number;
+    minPCost: number;
+    maxPCost: number;
+    minOutputLen: number;
+    maxOutputLen: number;
+    maxPasswordLen: number;
+    minSaltLen: number;
+    maxSaltLen: number;
+    maxSecretLen: number;
+    maxDataLen: number;
+  };
+};
+export type CapabilitiesFunctionType = () => Argon2Capabilities;
+
+export type Argon2Runtime = {
+  hash: HashFunctionType,
+  hashRaw: HashRawFunctionType,
+  verify: VerifyFunctionType,
+  needsRehash: NeedsRehashFunctionType,
+  verifyAndUpgrade: VerifyAndUpgradeFunctionType,
+  setVerifyPolicy: SetVerifyPolicyFunctionType,
+  createContext: CreateContextFunctionType,
+  beginHash: BeginHashFunctionType,
+  memoryStats: MemoryStatsFunctionType,
+  setMemoryLimit: SetMemoryLimitFunctionType,
+  isPoisoned: IsPoisonedFunctionType,
+  reset: ResetFunctionType,
+  terminate: TerminateFunctionType,
+  capabilities: CapabilitiesFunctionType,
+};
+
+export type { WasmOptions };
+
+/**
+ * Version of the wasm module's exports these bindings are written for. See
+ * `ABI_VERSION` in `wasm/capabilities.rs`.
+ */
+const ABI_VERSION = 1;
+
+/** Bits encoding algorithms and versions for the wasm module. */
+const ALGORITHM_BITS: Record<Argon2Algorithm, number> = { Argon2d: 1, Argon2i: 2, Argon2id: 4 };
+const VERSION_BITS: Record<Argon2Version, number> = { 0x10: 1, 0x13: 2 };
+
+export default async (_WebAssembly: typeof WebAssembly, options?: WasmOptions): Promise<Argon2Runtime> => {
+  const wasm = await wasmBuilder(_WebAssembly, options);
+
+  // Other versions may take different arguments or write different output,
+  // which calls would misread rather than fail on
+  if (wasm.abiVersion() !== ABI_VERSION) {
+    for (const worker of wasm.laneWorkers) {
+      worker.terminate();
+    }
+    throw new Error(
+      `The wasm module has ABI version ${wasm.abiVersion()}, but these bindings need version ${ABI_VERSION}`,
+    );
+  }
 
   function bufferSourceArrayBuffer(data: BufferSource) {
     if (ArrayBuffer.isView(data)) {
@@ -69,7 +314,8 @@ export default async (_WebAssembly: typeof WebAssembly): Promise<Argon2Runtime>
   /**
    * Transfers an {@link ArrayBufferLike} to wasm, automatically allocating it in memory.
    *
-   * Remember to unallocate the transfered buffer with {@link wasm.dealloc}
+   * Remember to unallocate the transfered buffer with {@link wasm.dealloc}, or
+   * {@link wasm.deallocZeroize} if it holds a password, secret or key.
    */
   function transfer(buffer: BufferSource): [number, number] {
     const length = buffer.byteLength;
@@ -87,26 +333,133 @@ export default async (_WebAssembly: typeof WebAssembly): Promise<Argon2Runtime>
     return [0, 0];
   }
 
+  /**
+   * Reads a pointer or size the wasm module wrote to `ptr`, which is 64-bit in
+   * the memory64 build.
+   */
+  function readPointer(ptr: number): number {
+    const view = new DataView(wasm.memory.buffer, ptr, wasm.pointerSize);
+    return wasm.pointerSize === 8
+      ? Number(view.getBigUint64(0, true)) // WASM is little endian
+      : view.getUint32(0, true);
+  }
 
   /**
-   * Computes the Argon2 hash digest for the password, salt and parameters.
+   * Reads the message the wasm module recorded for its most recent failure.
    */
-  function hash(
-    password: BufferSource,
-    salt: BufferSource,
-    params?: Argon2Params,
-  ): string {
-    params ??= {
-      algorithm: "Argon2id",
-      version: 0x13,
+  function lastError(): string {
+    const size = wasm.pointerSize;
+    const outputPtr = wasm.alloc(2 * size); // pointer and length of the message
+    wasm.lastError(outputPtr, outputPtr + size);
+
+    const messagePtr = readPointer(outputPtr);
+    const messageLen = readPointer(outputPtr + size);
+    wasm.dealloc(outputPtr, 2 * size);
+
+    return new TextDecoder().decode(
+      new Uint8Array(wasm.memory.buffer, messagePtr, messageLen).slice(),
+    );
+  }
+
+  /**
+   * Throws an {@link Argon2Error} if a wasm call returned a non-zero status.
+   */
+  function check(status: number) {
+    if (status !== 0) {
+      const thrown = callbackError;
+      callbackError = undefined;
+      if (thrown) {
+        throw thrown.error;
+      }
+      throw new Argon2Error(status, lastError());
+    }
+  }
+
+
+  /**
+   * Error thrown by a callback during the last wasm call, rethrown by
+   * {@link check} in place of the call's status.
+   */
+  let callbackError: { error: unknown } | undefined;
+
+  /**
+   * Makes a wasm call with the callbacks requested by the caller installed.
+   * A callback that throws cancels the call.
+   */
+  function withCallbacks(options: VerifyOptions, call: () => number): number {
+    const { shouldCancel, onProgress } = options;
+    callbackError = undefined;
+
+    wasm.callbacks.shouldCancel = () => {
+      if (callbackError) {
+        return true;
+      }
+      try {
+        return shouldCancel?.() ?? false;
+      } catch (error) {
+        callbackError = { error };
+        return true;
+      }
     };
+    if (onProgress) {
+      wasm.callbacks.progress = (pass, slice, lane, fraction) => {
+        try {
+          onProgress({ pass, slice, lane, fraction });
+        } catch (error) {
+          callbackError ??= { error };
+        }
+      };
+    }
+
+    try {
+      return call();
+    } finally {
+      wasm.callbacks.shouldCancel = undefined;
+      wasm.callbacks.progress = undefined;
+    }
+  }
+
+  /**
+   * Copies a NUL-terminated digest out of wasm memory and frees it.
+   */
+  function takeDigest(outputPtr: number): string {
+    const outputMemory = new DataView(wasm.memory.buffer, outputPtr);
+    let outputSize = 0;
+    for (outputSize = 0; outputMemory.getUint8(outputSize); outputSize++);
+
+    // Copy output from wasm memory into js
+    const outputBuf = new ArrayBuffer(outputSize);
+    new Uint8Array(outputBuf).set(
+      new Uint8Array(wasm.memory.buffer, outputPtr, outputSize),
+    );
+    wasm.deallocZeroize(outputPtr, outputSize + 1);
+
+    const digest = new TextDecoder().decode(outputBuf);
+    new Uint8Array(outputBuf).fill(0);
+    return digest;
+  }
+
+  /**
+   * Returns a copy of `params` with the default costs for the chosen
+   * algorithm filled in, leaving the caller's object untouched.
+   */
+  function withDefaults(params?: Argon2Params): ResolvedParams {
+    const resolved = { ...(params ?? { algorithm: "Argon2id", version: 0x13 }) };
     // These defaults come from https://cheatsheetseries.owasp.org/cheatsheets/Password_Storage_Cheat_Sheet.html#argon2id
-    params.mCost ??= params.algorithm === "Argon2i" ? 12288 : 19456;
-    params.tCost ??= params.algorithm === "Argon2i" ? 3 : 2;
-    params.pCost ??= 1;
+    resolved.mCost ??= resolved.algorithm === "Argon2i" ? 12288 : 19456;
+    resolved.tCost ??= resolved.algorithm === "Argon2i" ? 3 : 2;
+    resolved.pCost ??= 1;
+    resolved.outputLen ??= 32;
 
+    return resolved as ResolvedParams;
+  }
+
+  /**
+   * Encodes an algorithm as the 4-byte tag the wasm module expects.
+   */
+  function algorithmTag(algorithm: Argon2Algorithm): number {
     let algorithmBuf: Uint8Array;
-    switch (params.algorithm) {
+    switch (algorithm) {
       case "Argon2i":
         algorithmBuf = new TextEncoder().encode("i___");
         break;
@@ -117,54 +470,116 @@ export default async (_WebAssembly: typeof WebAssembly): Promise<Argon2Runtime>
         algorithmBuf = new TextEncoder().encode("id__");
         break;
     }
-    const algorithm = new DataView(algorithmBuf.buffer).getUint32(0, true); // WASM is little endian
-    
+    return new DataView(algorithmBuf.buffer).getUint32(0, true); // WASM is little endian
+  }
+
+  /**
+   * Computes the Argon2 hash digest for the password, salt and parameters.
+   */
+  function hash(
+    password: BufferSource,
+    salt: BufferSource,
+    _params?: Argon2Params,
+  ): string {
+    const params = withDefaults(_params);
+    const algorithm = algorithmTag(params.algorithm);
 
     const [passwordPtr, passwordLen] = transfer(password);
     const [saltPtr, saltLen] = transfer(salt);
-    const [secretPtr, secretLen] = maybeTransfer(params?.secret);
-    const outputLocPtr = wasm.alloc(4); // pointer to output data
+    const [secretPtr, secretLen] = maybeTransfer(params.secret);
+    const [dataPtr, dataLen] = maybeTransfer(params.data);
+    const outputLocPtr = wasm.alloc(wasm.pointerSize); // pointer to output data
 
-    // Load Argon2 params into WASM memory
-    wasm.setupParams(
-      algorithm,
-      params.version,
-      params.mCost,
-      params.tCost,
-      params.pCost,
+    const status = withCallbacks(params, () =>
+      wasm.hashWithParams(
+        passwordPtr,
+        passwordLen,
+        saltPtr,
+        saltLen,
+        secretPtr,
+        secretLen,
+        dataPtr,
+        dataLen,
+        algorithm,
+        params.version,
+        params.mCost,
+        params.tCost,
+        params.pCost,
+        params.outputLen,
+        outputLocPtr,
+      ),
     );
 
-    wasm.hash(
-      passwordPtr,
-      passwordLen,
-      saltPtr,
-      saltLen,
-      secretPtr,
-      secretLen,
-      outputLocPtr,
+    wasm.deallocZeroize(passwordPtr, passwordLen);
+    wasm.dealloc(saltPtr, saltLen);
+    if (secretPtr !== 0) {
+      wasm.deallocZeroize(secretPtr, secretLen);
+    }
+    if (dataPtr !== 0) {
+      wasm.dealloc(dataPtr, dataLen);
+    }
+
+    const outputPtr = readPointer(outputLocPtr);
+    wasm.dealloc(outputLocPtr, wasm.pointerSize);
+    check(status);
+
+    return takeDigest(outputPtr);
+  }
+
+  /**
+   * Computes the raw Argon2 tag of `length` bytes for the password, salt and
+   * parameters, for use as a derived key.
+   */
+  function hashRaw(
+    password: BufferSource,
+    salt: BufferSource,
+    length: number,
+    _params?: Argon2Params,
+  ): Uint8Array {
+    const params = withDefaults(_params);
+
+    const [passwordPtr, passwordLen] = transfer(password);
+    const [saltPtr, saltLen] = transfer(salt);
+    const [secretPtr, secretLen] = maybeTransfer(params.secret);
+    const [dataPtr, dataLen] = maybeTransfer(params.data);
+    const outputPtr = wasm.alloc(length);
+
+    const status = withCallbacks(params, () =>
+      wasm.hashRaw(
+        passwordPtr,
+        passwordLen,
+        saltPtr,
+        saltLen,
+        secretPtr,
+        secretLen,
+        dataPtr,
+        dataLen,
+        algorithmTag(params.algorithm),
+        params.version,
+        params.mCost,
+        params.tCost,
+        params.pCost,
+        outputPtr,
+        length,
+      ),
     );
 
-    wasm.dealloc(passwordPtr, passwordLen);
+    wasm.deallocZeroize(passwordPtr, passwordLen);
     wasm.dealloc(saltPtr, saltLen);
     if (secretPtr !== 0) {
-      wasm.dealloc(secretPtr, secretLen);
+      wasm.deallocZeroize(secretPtr, secretLen);
+    }
+    if (dataPtr !== 0) {
+      wasm.dealloc(dataPtr, dataLen);
     }
-
-    const outputPtr = new DataView(wasm.memory.buffer, outputLocPtr, 4).getUint32(0, true); // WASM is little endian
-    wasm.dealloc(outputLocPtr, 4);
-    
-    const outputMemory = new DataView(wasm.memory.buffer, outputPtr);
-    let outputSize = 0;
-    for (outputSize = 0; outputMemory.getUint8(outputSize); outputSize++);
 
     // Copy output from wasm memory into js
-    const outputBuf = new ArrayBuffer(outputSize);
-    new Uint8Array(outputBuf).set(
-      new Uint8Array(wasm.memory.buffer, outputPtr, outputSize),
-    );
-    wasm.dealloc(outputPtr, outputSize + 1);
+    const output = new Uint8Array(length);
+    output.set(new Uint8Array(wasm.memory.buffer, outputPtr, length));
+    wasm.deallocZeroize(outputPtr, length);
+    check(status);
 
-    return new TextDecoder().decode(outputBuf);
+    return output;
   }
 
   /**
@@ -173,34 +588,476 @@ export default async (_WebAssembly: typeof WebAssembly): Promise<Argon2Runtime>
   function verify(
     digest: string,
     password: BufferSource,
-    secret?: BufferSource
+    secret?: BufferSource,
+    options?: VerifyOptions,
   ): boolean {
     const [digestPtr, digestLen] = transfer(new TextEncoder().encode(digest));
     const [passwordPtr, passwordLen] = transfer(password);
     const [secretPtr, secretLen] = maybeTransfer(secret);
     const matchesPtr = wasm.alloc(4); // pointer to output data
 
-    wasm.verify(
+    const status = withCallbacks(options ?? {}, () =>
+      wasm.verify(
+        digestPtr,
+        digestLen,
+        passwordPtr,
+        passwordLen,
+        secretPtr,
+        secretLen,
+        matchesPtr,
+      ),
+    );
+
+    wasm.dealloc(digestPtr, digestLen);
+    wasm.deallocZeroize(passwordPtr, passwordLen);
+    if (secretPtr !== 0) {
+      wasm.deallocZeroize(secretPtr, secretLen);
+    }
+
+    const matches = !!new DataView(wasm.memory.buffer, matchesPtr, 4).getUint32(0, true); // WASM is little endian
+    wasm.dealloc(matchesPtr, 4);
+    check(status);
+
+    return matches;
+  }
+
+  /**
+   * Checks whether a digest was produced with weaker settings than `params`
+   * (the same defaults as {@link hash} apply) or with a salt shorter than
+   * `saltLen` bytes. This only parses the digest and is cheap to call.
+   */
+  function needsRehash(
+    digest: string,
+    _params?: Argon2Params,
+    saltLen = 16,
+  ): boolean {
+    const params = withDefaults(_params);
+
+    const [digestPtr, digestLen] = transfer(new TextEncoder().encode(digest));
+    const outdatedPtr = wasm.alloc(4); // pointer to output data
+
+    const status = wasm.needsRehash(
       digestPtr,
       digestLen,
+      algorithmTag(params.algorithm),
+      params.version,
+      params.mCost,
+      params.tCost,
+      params.pCost,
+      params.outputLen,
+      saltLen,
+      outdatedPtr,
+    );
+
+    wasm.dealloc(digestPtr, digestLen);
+
+    const outdated = !!new DataView(wasm.memory.buffer, outdatedPtr, 4).getUint32(0, true); // WASM is little endian
+    wasm.dealloc(outdatedPtr, 4);
+    check(status);
+
+    return outdated;
+  }
+
+  /**
+   * Verifies a password and, if it matches a digest that {@link needsRehash}
+   * with `params`, rehashes it with `params` and the fresh `salt` in the same
+   * call. `params.secret` is used both to verify and to rehash.
+   */
+  function verifyAndUpgrade(
+    digest: string,
+    password: BufferSource,
+    salt: BufferSource,
+    _params?: Argon2Params,
+  ): { matches: boolean; digest?: string } {
+    const params = withDefaults(_params);
+
+    const [digestPtr, digestLen] = transfer(new TextEncoder().encode(digest));
+    const [passwordPtr, passwordLen] = transfer(password);
+    const [secretPtr, secretLen] = maybeTransfer(params.secret);
+    const [saltPtr, saltLen] = transfer(salt);
+    const [dataPtr, dataLen] = maybeTransfer(params.data);
+    const matchesPtr = wasm.alloc(4); // pointer to output data
+    const outputLocPtr = wasm.alloc(wasm.pointerSize); // pointer to output data
+
+    const status = withCallbacks(params, () =>
+      wasm.verifyAndUpgrade(
+        digestPtr,
+        digestLen,
+        passwordPtr,
+        passwordLen,
+        secretPtr,
+        secretLen,
+        saltPtr,
+        saltLen,
+        dataPtr,
+        dataLen,
+        algorithmTag(params.algorithm),
+        params.version,
+        params.mCost,
+        params.tCost,
+        params.pCost,
+        params.outputLen,
+        matchesPtr,
+        outputLocPtr,
+      ),
+    );
+
+    wasm.dealloc(digestPtr, digestLen);
+    wasm.deallocZeroize(passwordPtr, passwordLen);
+    if (secretPtr !== 0) {
+      wasm.deallocZeroize(secretPtr, secretLen);
+    }
+    wasm.dealloc(saltPtr, saltLen);
+    if (dataPtr !== 0) {
+      wasm.dealloc(dataPtr, dataLen);
+    }
+
+    const matches = !!new DataView(wasm.memory.buffer, matchesPtr, 4).getUint32(0, true); // WASM is little endian
+    const outputPtr = readPointer(outputLocPtr);
+    wasm.dealloc(matchesPtr, 4);
+    wasm.dealloc(outputLocPtr, wasm.pointerSize);
+    check(status);
+
+    if (outputPtr === 0) {
+      return { matches };
+    }
+    return { matches, digest: takeDigest(outputPtr) };
+  }
+
+  /**
+   * Sets the {@link VerifyPolicy} used by every later {@link verify} and
+   * {@link verifyAndUpgrade} call on this instance.
+   */
+  function setVerifyPolicy(policy: VerifyPolicy) {
+    wasm.setVerifyPolicy(
+      policy.maxMCost ?? 0xFFFFFFFF,
+      policy.maxTCost ?? 0xFFFFFFFF,
+      policy.maxPCost ?? 0xFFFFFFFF,
+      (policy.algorithms ?? ["Argon2d", "Argon2i", "Argon2id"])
+        .reduce((bits, algorithm) => bits | ALGORITHM_BITS[algorithm], 0),
+      (policy.versions ?? [0x10, 0x13])
+        .reduce((bits, version) => bits | VERSION_BITS[version], 0),
+    );
+  }
+
+  /**
+   * Creates an {@link Argon2Context} for the parameters. `params.secret` and
+   * `params.data` are used by every call made through the context.
+   */
+  function createContext(_params?: Argon2Params): Argon2Context {
+    const params = withDefaults(_params);
+
+    const handlePtr = wasm.alloc(4); // pointer to output data
+    const status = wasm.contextNew(
+      algorithmTag(params.algorithm),
+      params.version,
+      params.mCost,
+      params.tCost,
+      params.pCost,
+      params.outputLen,
+      handlePtr,
+    );
+    const handle = new DataView(wasm.memory.buffer, handlePtr, 4).getUint32(0, true); // WASM is little endian
+    wasm.dealloc(handlePtr, 4);
+    check(status);
+
+    function hash(password: BufferSource, salt: BufferSource): string {
+      const [passwordPtr, passwordLen] = transfer(password);
+      const [saltPtr, saltLen] = transfer(salt);
+      const [secretPtr, secretLen] = maybeTransfer(params.secret);
+      const [dataPtr, dataLen] = maybeTransfer(params.data);
+      const outputLocPtr = wasm.alloc(wasm.pointerSize); // pointer to output data
+
+      const status = withCallbacks(params, () =>
+        wasm.contextHash(
+          handle,
+          passwordPtr,
+          passwordLen,
+          saltPtr,
+          saltLen,
+          secretPtr,
+          secretLen,
+          dataPtr,
+          dataLen,
+          outputLocPtr,
+        ),
+      );
+
+      wasm.deallocZeroize(passwordPtr, passwordLen);
+      wasm.dealloc(saltPtr, saltLen);
+      if (secretPtr !== 0) {
+        wasm.deallocZeroize(secretPtr, secretLen);
+      }
+      if (dataPtr !== 0) {
+        wasm.dealloc(dataPtr, dataLen);
+      }
+
+      const outputPtr = readPointer(outputLocPtr);
+      wasm.dealloc(outputLocPtr, wasm.pointerSize);
+      check(status);
+
+      return takeDigest(outputPtr);
+    }
+
+    function verify(digest: string, password: BufferSource): boolean {
+      const [digestPtr, digestLen] = transfer(new TextEncoder().encode(digest));
+      const [passwordPtr, passwordLen] = transfer(password);
+      const [secretPtr, secretLen] = maybeTransfer(params.secret);
+      const matchesPtr = wasm.alloc(4); // pointer to output data
+
+      const status = withCallbacks(params, () =>
+        wasm.contextVerify(
+          handle,
+          digestPtr,
+          digestLen,
+          passwordPtr,
+          passwordLen,
+          secretPtr,
+          secretLen,
+          matchesPtr,
+        ),
+      );
+
+      wasm.dealloc(digestPtr, digestLen);
+      wasm.deallocZeroize(passwordPtr, passwordLen);
+      if (secretPtr !== 0) {
+        wasm.deallocZeroize(secretPtr, secretLen);
+      }
+
+      const matches = !!new DataView(wasm.memory.buffer, matchesPtr, 4).getUint32(0, true); // WASM is little endian
+      wasm.dealloc(matchesPtr, 4);
+      check(status);
+
+      return matches;
+    }
+
+    function free() {
+      check(wasm.contextFree(handle));
+    }
+
+    return { hash, verify, free };
+  }
+
+  /**
+   * Starts an {@link Argon2HashJob}. The password and secret are consumed
+   * immediately; `params.shouldCancel` and `params.onProgress` are not used,
+   * since the caller decides when each step runs.
+   */
+  function beginHash(
+    password: BufferSource,
+    salt: BufferSource,
+    _params?: Argon2Params,
+  ): Argon2HashJob {
+    const params = withDefaults(_params);
+
+    const [passwordPtr, passwordLen] = transfer(password);
+    const [saltPtr, saltLen] = transfer(salt);
+    const [secretPtr, secretLen] = maybeTransfer(params.secret);
+    const [dataPtr, dataLen] = maybeTransfer(params.data);
+    const jobPtr = wasm.alloc(4); // pointer to output data
+
+    const status = wasm.hashBegin(
       passwordPtr,
       passwordLen,
+      saltPtr,
+      saltLen,
       secretPtr,
       secretLen,
-      matchesPtr,
+      dataPtr,
+      dataLen,
+      algorithmTag(params.algorithm),
+      params.version,
+      params.mCost,
+      params.tCost,
+      params.pCost,
+      params.outputLen,
+      jobPtr,
     );
 
-    wasm.dealloc(digestPtr, digestLen);
-    wasm.dealloc(passwordPtr, passwordLen);
+    wasm.deallocZeroize(passwordPtr, passwordLen);
+    wasm.dealloc(saltPtr, saltLen);
     if (secretPtr !== 0) {
-      wasm.dealloc(secretPtr, secretLen);
+      wasm.deallocZeroize(secretPtr, secretLen);
+    }
+    if (dataPtr !== 0) {
+      wasm.dealloc(dataPtr, dataLen);
     }
 
-    const matches = !!new DataView(wasm.memory.buffer, matchesPtr, 4).getUint32(0, true); // WASM is little endian
-    wasm.dealloc(matchesPtr, 4);
+    const job = new DataView(wasm.memory.buffer, jobPtr, 4).getUint32(0, true); // WASM is little endian
+    wasm.dealloc(jobPtr, 4);
+    check(status);
 
-    return matches;
+    function step(budget = 0xFFFFFFFF): boolean {
+      const donePtr = wasm.alloc(4); // pointer to output data
+      const status = wasm.hashStep(job, budget, donePtr);
+
+      const done = !!new DataView(wasm.memory.buffer, donePtr, 4).getUint32(0, true); // WASM is little endian
+      wasm.dealloc(donePtr, 4);
+      check(status);
+
+      return done;
+    }
+
+    function finish(): string {
+      const outputLocPtr = wasm.alloc(wasm.pointerSize); // pointer to output data
+      const status = wasm.hashFinish(job, outputLocPtr);
+
+      const outputPtr = readPointer(outputLocPtr);
+      wasm.dealloc(outputLocPtr, wasm.pointerSize);
+      check(status);
+
+      return takeDigest(outputPtr);
+    }
+
+    function finishRaw(): Uint8Array {
+      const length = params.outputLen;
+      const outputPtr = wasm.alloc(length);
+      const status = wasm.hashFinishRaw(job, outputPtr, length);
+
+      // Copy output from wasm memory into js
+      const output = new Uint8Array(length);
+      output.set(new Uint8Array(wasm.memory.buffer, outputPtr, length));
+      wasm.deallocZeroize(outputPtr, length);
+      check(status);
+
+      return output;
+    }
+
+    function abort() {
+      check(wasm.hashAbort(job));
+    }
+
+    return { step, finish, finishRaw, abort };
+  }
+
+  /**
+   * Reads the allocator statistics of this instance. Reading them doesn't
+   * allocate, so they can be compared before and after a call to spot leaks.
+   */
+  function memoryStats(): MemoryStats {
+    const statsPtr = wasm.memoryStats();
+    const size = wasm.pointerSize;
+
+    return {
+      allocatedBytes: readPointer(statsPtr),
+      peakBytes: readPointer(statsPtr + size),
+      liveAllocations: readPointer(statsPtr + 2 * size),
+      pages: readPointer(statsPtr + 3 * size),
+    };
+  }
+
+  /**
+   * Caps the bytes this instance may have allocated at once, or removes the
+   * cap when `maxBytes` is omitted. Calls whose Argon2 memory would exceed it
+   * throw an {@link Argon2Error} with {@link Argon2ErrorCode.OutOfMemory} and
+   * leave the instance usable. Limits beyond what the build can address
+   * remove the cap as well.
+   *
+   * @throws {RangeError} If `maxBytes` is negative or not finite.
+   */
+  function setMemoryLimit(maxBytes?: number) {
+    if (maxBytes !== undefined && !(Number.isFinite(maxBytes) && maxBytes >= 0)) {
+      throw new RangeError(`Memory limit must be a finite, non-negative number of bytes. Got ${maxBytes} instead.`);
+    }
+
+    // usize::MAX in the 32-bit builds, which would otherwise truncate larger
+    // limits. The memory64 build's is out of range for a number, but no
+    // memory gets near the largest safe integer.
+    const max = wasm.pointerSize === 8 ? Number.MAX_SAFE_INTEGER : 0xFFFFFFFF;
+    wasm.setMemoryLimit(Math.min(Math.floor(maxBytes ?? max), max));
+  }
+
+  /**
+   * Whether Rust code in the module has panicked since instantiation or the
+   * last {@link reset}. Other traps, such as running out of stack, and errors
+   * thrown by imports don't set it.
+   */
+  function isPoisoned(): boolean {
+    return !!wasm.isPoisoned();
+  }
+
+  /**
+   * Recovers the instance after a trap without reinstantiating it. Everything
+   * the module allocated is freed: contexts and hash jobs created before the
+   * reset fail with {@link Argon2ErrorCode.InvalidHandle}, and any pointer into
+   * the module's memory held elsewhere must no longer be used. The verify
+   * policy and memory limit are kept.
+   */
+  function reset() {
+    wasm.reset();
+  }
+
+  /**
+   * Stops the lane workers of the threaded build, which otherwise keep the
+   * process alive. Hashing still works afterwards, on one thread. Must not be
+   * called from a callback while hashing.
+   */
+  function terminate() {
+    // A worker may be terminated while counted as busy, so the module must
+    // stop waiting for them first
+    wasm.stopLaneWorkers();
+    for (const worker of wasm.laneWorkers) {
+      worker.terminate();
+    }
+  }
+
+  /**
+   * Reports what the loaded build supports, read from the `Capabilities` of
+   * `wasm/capabilities.rs`, whose fields are all u32.
+   */
+  function capabilities(): Argon2Capabilities {
+    const fields = new DataView(wasm.memory.buffer, wasm.capabilities(), 16 * 4);
+    const field = (index: number) => fields.getUint32(4 * index, true); // WASM is little endian
+    const feature = (bit: number) => (field(2) & (1 << bit)) !== 0;
+
+    return {
+      abiVersion: wasm.abiVersion(),
+      algorithms: (Object.keys(ALGORITHM_BITS) as Argon2Algorithm[])
+        .filter((algorithm) => field(0) & ALGORITHM_BITS[algorithm]),
+      versions: ([0x10, 0x13] as const)
+        .filter((version) => field(1) & VERSION_BITS[version]),
+      features: {
+        secret: feature(0),
+        associatedData: feature(1),
+        rawOutput: feature(2),
+        threads: feature(3),
+        simd: feature(4),
+        atomics: feature(5),
+        memory64: feature(6),
+        scratch: feature(7),
+      },
+      limits: {
+        minMCost: field(3),
+        maxMCost: field(4),
+        minTCost: field(5),
+        maxTCost: field(6),
+        minPCost: field(7),
+        maxPCost: field(8),
+        minOutputLen: field(9),
+        maxOutputLen: field(10),
+        maxPasswordLen: field(11),
+        minSaltLen: field(12),
+        maxSaltLen: field(13),
+        maxSecretLen: field(14),
+        maxDataLen: field(15),
+      },
+    };
   }
 
-  return { hash, verify };
+  return {
+    hash,
+    hashRaw,
+    verify,
+    needsRehash,
+    verifyAndUpgrade,
+    setVerifyPolicy,
+    createContext,
+    beginHash,
+    memoryStats,
+    setMemoryLimit,
+    isPoisoned,
+    reset,
+    terminate,
+    capabilities,
+  };
 };
@@ -0,0 +1,44 @@
+import buildWithRuntime, { type Argon2Runtime } from "./mod_runtime_agnostic.ts";
+export type * from "./mod_runtime_agnostic.ts";
+export { Argon2Error, Argon2ErrorCode } from "./mod_runtime_agnostic.ts";
+import { source } from "./wasm/wasm_scratch.js";
+
+// The scratch build keeps Argon2 memory blocks in buffers outside the
+// module's memory, dropped after each hash or with their context, so a large
+// `mCost` doesn't grow the module's memory for good. Hashing is slower: each
+// 1 KiB block computed takes two or three calls out of the module to copy
+// blocks in and out of the buffers, on top of the compression itself. Compare
+// both builds on your runtime with `deno task bench` after
+// `deno task build:scratch`.
+const scratchRuntime: Argon2Runtime = await buildWithRuntime(WebAssembly, {
+  module: await WebAssembly.compile(await source(WebAssembly)),
+});
+const hash = scratchRuntime.hash;
+const hashRaw = scratchRuntime.hashRaw;
+const verify = scratchRuntime.verify;
+const needsRehash = scratchRuntime.needsRehash;
+const verifyAndUpgrade = scratchRuntime.verifyAndUpgrade;
+const setVerifyPolicy = scratchRuntime.setVerifyPolicy;
+const createContext = scratchRuntime.createContext;
+const beginHash = scratchRuntime.beginHash;
+const memoryStats = scratchRuntime.memoryStats;
+const setMemoryLimit = scratchRuntime.setMemoryLimit;
+const isPoisoned = scratchRuntime.isPoisoned;
+const reset = scratchRuntime.reset;
+const capabilities = scratchRuntime.capabilities;
+
+export {
+  beginHash,
+  capabilities,
+  createContext,
+  hash,
+  hashRaw,
+  isPoisoned,
+  memoryStats,
+  needsRehash,
+  reset,
+  setMemoryLimit,
+  setVerifyPolicy,
+  verify,
+  verifyAndUpgrade,
+};
@@ -0,0 +1,44 @@
+import buildWithRuntime, { type Argon2Runtime } from "./mod_runtime_agnostic.ts";
+export type * from "./mod_runtime_agnostic.ts";
+export { Argon2Error, Argon2ErrorCode } from "./mod_runtime_agnostic.ts";
+import { source } from "./wasm/wasm_threads.js";
+import { createSharedMemory } from "./wasm/thread.ts";
+
+// The threaded build computes the lanes of a hash on workers sharing its
+// memory. Lanes only run in parallel when `pCost` is above 1.
+const threadsRuntime: Argon2Runtime = await buildWithRuntime(WebAssembly, {
+  module: await WebAssembly.compile(await source(WebAssembly)),
+  memory: createSharedMemory(),
+  laneWorkers: navigator.hardwareConcurrency - 1,
+});
+const hash = threadsRuntime.hash;
+const hashRaw = threadsRuntime.hashRaw;
+const verify = threadsRuntime.verify;
+const needsRehash = threadsRuntime.needsRehash;
+const verifyAndUpgrade = threadsRuntime.verifyAndUpgrade;
+const setVerifyPolicy = threadsRuntime.setVerifyPolicy;
+const createContext = threadsRuntime.createContext;
+const beginHash = threadsRuntime.beginHash;
+const memoryStats = threadsRuntime.memoryStats;
+const setMemoryLimit = threadsRuntime.setMemoryLimit;
+const isPoisoned = threadsRuntime.isPoisoned;
+const reset = threadsRuntime.reset;
+const capabilities = threadsRuntime.capabilities;
+const terminate = threadsRuntime.terminate;
+
+export {
+  beginHash,
+  capabilities,
+  createContext,
+  hash,
+  hashRaw,
+  isPoisoned,
+  memoryStats,
+  needsRehash,
+  reset,
+  setMemoryLimit,
+  setVerifyPolicy,
+  terminate,
+  verify,
+  verifyAndUpgrade,
+};
@@ -4,32 +4,104 @@ import { compress } from "@blckbrry/lz4";
 // Generate wasm
 {
   const isTiny = !!Deno.env.get("TINY");
+  // The shared-memory builds need core and alloc rebuilt with atomics, and
+  // import memory whose limits must match createSharedMemory in
+  // wasm/thread.ts. THREADS also enables lane workers.
+  const shared = Deno.env.get("THREADS") ? "threads" : Deno.env.get("ATOMICS") ? "atomics" : undefined;
+  // The memory64 build targets wasm64, a tier 3 target that needs core and
+  // alloc rebuilt as well
+  const memory64 = !shared && !!Deno.env.get("MEMORY64");
+  const target = memory64 ? "wasm64-unknown-unknown" : "wasm32-unknown-unknown";
+  // The scratch build keeps Argon2 blocks in buffers provided by wasm/mod.ts
+  const scratch = !shared && !memory64 && !!Deno.env.get("SCRATCH");
+  const variant = shared ?? (memory64 ? "memory64" : scratch ? "scratch" : undefined);
+  // WEE_ALLOC swaps dlmalloc for wee_alloc, whose code is smaller, in the
+  // default and scratch builds. The others need dlmalloc's lock or wasm64.
+  const weeAlloc = !shared && !memory64 && !!Deno.env.get("WEE_ALLOC");
+  const allocatorArgs = weeAlloc ? ["--no-default-features", "--features", "wee_alloc"] : [];
   const name = "xenon2";
-  
-  await new Deno.Command("cargo", {
+
+  // RUSTFLAGS replaces the flags in .cargo/config.toml, so builds set it to
+  // the caller's flags followed by the stack pointer export and their own
+  const callerFlags = Deno.env.get("RUSTFLAGS")?.trim();
+  const baseFlags = [
+    ...(callerFlags ? [callerFlags] : []),
+    "-C link-arg=--export=__stack_pointer",
+  ];
+
+  /**
+   * Builds the module with `rustflags` appended to the base flags, and returns
+   * it compressed and encoded.
+   */
+  async function build(rustflags: string[] = []): Promise<string> {
+    const { success } = await new Deno.Command("cargo", {
+      
+      // args: ["build", "--target", "wasm32-unknown-unknown"],
+      args: shared ? [
+        "+nightly", "build",
+        "-Z", "build-std=core,alloc",
+        "--release",
+        "--features", shared,
+        "--target", target,
+      ] : memory64 ? [
+        "+nightly", "build",
+        "-Z", "build-std=core,alloc",
+        "--release",
+        "--target", target,
+      ] : scratch ? [
+        "build",
+        "--release",
+        "--features", "scratch",
+        ...allocatorArgs,
+        "--target", target,
+      ] : !isTiny ? ["build", "--release", ...allocatorArgs, "--target", "wasm32-unknown-unknown"] : [
+        "+nightly", "build",
+        "-Z", "build-std=std,panic_abort",
+        "-Z", "build-std-features=panic_immediate_abort",
+        "--profile", "tiny",
+        ...allocatorArgs,
+        "--target", "wasm32-unknown-unknown",
+      ],
+      env: { RUSTFLAGS: [...baseFlags, ...rustflags].join(" ") },
+    }).spawn().status;
+    if (!success) {
+      throw new Error(`cargo build failed for ${target}`);
+    }
+
+    const targetFolder = Deno.env.get("CARGO_TARGET_DIR") || "target";
     
-    // args: ["build", "--target", "wasm32-unknown-unknown"],
-    args: !isTiny ? ["build", "--release", "--target", "wasm32-unknown-unknown"] : [
-      "+nightly", "build",
-      "-Z", "build-std=std,panic_abort",
-      "-Z", "build-std-features=panic_immediate_abort",
-      "--profile", "tiny",
-      "--target", "wasm32-unknown-unknown",
-    ],
-  }).spawn().status;
-  
-  const targetFolder = Deno.env.get("CARGO_TARGET_DIR") || "target";
-  
-  const wasm = await Deno.readFile(
-    `./${targetFolder}/wasm32-unknown-unknown/${isTiny ? "tiny" : "release"}/${name}.wasm`,
-    // `./${targetFolder}/wasm32-unknown-unknown/debug/${name}.wasm`,
-  );
-  const encoded = encodeBase64(compress(wasm));
-  // const encoded = encodeBase64(wasm);
+    const wasm = await Deno.readFile(
+      `./${targetFolder}/${target}/${isTiny && !variant ? "tiny" : "release"}/${name}.wasm`,
+      // `./${targetFolder}/wasm32-unknown-unknown/debug/${name}.wasm`,
+    );
+    return encodeBase64(compress(wasm));
+    // return encodeBase64(wasm);
+  }
+
+  // Each source is exported as a function decompressing it on demand
+  const sources: Record<string, string> = shared ? {
+    source: await build([
+      "-C target-feature=+atomics,+bulk-memory,+mutable-globals",
+      "-C link-arg=--shared-memory",
+      "-C link-arg=--import-memory",
+      "-C link-arg=--initial-memory=2097152",
+      "-C link-arg=--max-memory=4294967296",
+      // Used by wasm/thread.ts
+      "-C link-arg=--export=__wasm_init_tls",
+      "-C link-arg=--export=__tls_size",
+    ]),
+  } : memory64 || scratch ? {
+    source: await build(),
+  } : {
+    source: await build(),
+    // Picked by wasm/mod.ts where SIMD is supported
+    simdSource: await build(["-C target-feature=+simd128"]),
+  };
+
   const js = `// deno-fmt-ignore-file\n// deno-lint-ignore-file
   import { decodeBase64 } from "jsr:@std/encoding@0.221/base64";
   import buildRuntime from "jsr:@blckbrry/lz4@0.1.6/runtime_agnostic";
-
-  export const source = async (WebAssembly) => (await buildRuntime(WebAssembly)).decompress(decodeBase64("${encoded}"));`;
-  await Deno.writeTextFile("wasm/wasm.js", js);
+${Object.entries(sources).map(([name, encoded]) => `
+  export const ${name} = async (WebAssembly) => (await buildRuntime(WebAssembly)).decompress(decodeBase64("${encoded}"));`).join("")}`;
+  await Deno.writeTextFile(`wasm/${variant ? `wasm_${variant}` : "wasm"}.js`, js);
 }
@@ -1,27 +1,38 @@
-import { assert, assertEquals } from "jsr:@std/assert@0.221";
+import { assert, assertEquals, assertThrows } from "jsr:@std/assert@0.221";
+import { encodeBase64 } from "@std/encoding/base64";
+import { encodeHex } from "@std/encoding/hex";
 
-import { Argon2Params, hash, verify } from "./mod.ts";
-
-const encoder = new TextEncoder();
-const encode = (str: string) => encoder.encode(str);
-
-const password = encode("here's a very cool password");
-const password2 = encode("here's a different, less-cool password");
-const salt = encode("xenon2's so cool");
-
-const TESTS: [Argon2Params, string][] = [
-  [
-    { algorithm: "Argon2id", version: 0x13, mCost: 65536 },
-    "$argon2id$v=19$m=65536,t=2,p=1$eGVub24yJ3Mgc28gY29vbA$l2g9IkHxa2w5HAL0YuofExQCjELI/9wyYkmrNHhoa28",
-  ],
-];
+import {
+  Argon2Algorithm,
+  Argon2Error,
+  Argon2ErrorCode,
+  Argon2Params,
+  Argon2Progress,
+  Argon2Version,
+  beginHash,
+  capabilities,
+  createContext,
+  hash,
+  hashRaw,
+  memoryStats,
+  needsRehash,
+  setMemoryLimit,
+  setVerifyPolicy,
+  verify,
+  verifyAndUpgrade,
+} from "./mod.ts";
+import wasmBuilder from "./wasm/mod.ts";
+import buildWithRuntime from "./mod_runtime_agnostic.ts";
+import { encode, password, password2, salt, TESTS } from "./test_fixtures.ts";
 
 for (const [params, digest] of TESTS) {
   const m = params.mCost ? ` m=${params.mCost}` : "";
   const t = params.tCost ? ` t=${params.tCost}` : "";
   const p = params.pCost ? ` p=${params.pCost}` : "";
+  const len = params.outputLen ? ` len=${params.outputLen}` : "";
+  const data = params.data ? " data" : "";
 
-  const spec = `${params.algorithm} 0x${params.version.toString(16)}${m}${t}${p}`;
+  const spec = `${params.algorithm} 0x${params.version.toString(16)}${m}${t}${p}${len}${data}`;
 
   Deno.test({
     name: `Hash   ${spec}`,
@@ -41,3 +52,509 @@ for (const [params, digest] of TESTS) {
     },
   });
 }
+
+function assertArgon2Error(fn: () => unknown, code: Argon2ErrorCode) {
+  const error = assertThrows(fn, Argon2Error);
+  assertEquals(error.code, code);
+}
+
+/** Copies `bytes` into a fresh allocation, returning its pointer and length. */
+function write(wasm: Awaited<ReturnType<typeof wasmBuilder>>, bytes: Uint8Array): [number, number] {
+  const ptr = wasm.alloc(bytes.length);
+  new Uint8Array(wasm.memory.buffer, ptr, bytes.length).set(bytes);
+  return [ptr, bytes.length];
+}
+
+Deno.test({
+  name: "Errors are returned as status codes",
+  fn: () => {
+    assertArgon2Error(
+      () => hash(password, encode("salt"), { algorithm: "Argon2id", version: 0x13 }),
+      Argon2ErrorCode.SaltTooShort,
+    );
+    assertArgon2Error(
+      () => hash(password, salt, { algorithm: "Argon2id", version: 0x13, pCost: 0 }),
+      Argon2ErrorCode.InvalidParams,
+    );
+    assertArgon2Error(
+      () => verify("$argon2id$not-a-digest", password),
+      Argon2ErrorCode.MalformedDigest,
+    );
+
+    // The instance is still usable after a failed call
+    const [params, digest] = TESTS[0];
+    assertEquals(hash(password, salt, params), digest);
+  },
+});
+
+Deno.test({
+  name: "Errors carry the module's last error message",
+  fn: () => {
+    const format = assertThrows(() => verify("not a digest", password), Argon2Error);
+    assertEquals(format.code, Argon2ErrorCode.MalformedDigest);
+    assert(format.message.startsWith("Invalid digest format"));
+
+    const params = assertThrows(
+      () => verify("$argon2id$v=19$m=1,t=2,p=1$eGVub24yJ3Mgc28gY29vbA$l2g9IkHxa2w5HAL0YuofExQCjELI/9wyYkmrNHhoa28", password),
+      Argon2Error,
+    );
+    assertEquals(params.code, Argon2ErrorCode.MalformedDigest);
+    assert(params.message.startsWith("Invalid digest parameters"));
+  },
+});
+
+Deno.test({
+  name: "Parameters don't leak between calls",
+  fn: () => {
+    const [params, digest] = TESTS[0];
+    hash(password, salt, { algorithm: "Argon2d", version: 0x10, mCost: 64, tCost: 1 });
+    assertEquals(hash(password, salt, params), digest);
+
+    // Defaults are filled in on a copy, not the caller's object
+    const partial: Argon2Params = { algorithm: "Argon2id", version: 0x13 };
+    hash(password, salt, partial);
+    assertEquals(partial, { algorithm: "Argon2id", version: 0x13 });
+  },
+});
+
+Deno.test({
+  name: "Raw hash matches the PHC digest's tag",
+  fn: () => {
+    const [params, digest] = TESTS[0];
+    const raw = hashRaw(password, salt, 32, params);
+    const tag = digest.slice(digest.lastIndexOf("$") + 1);
+    assertEquals(encodeBase64(raw).replace(/=+$/, ""), tag);
+
+    assertEquals(hashRaw(password, salt, 64, params).length, 64);
+  },
+});
+
+// RFC 9106 section 5 test vectors, and the reference implementation's 0x10 ones
+const REFERENCE_VECTORS: [Argon2Algorithm, Argon2Version, string][] = [
+  ["Argon2d", 0x13, "512b391b6f1162975371d30919734294f868e3be3984f3c1a13a4db9fabe4acb"],
+  ["Argon2i", 0x13, "c814d9d1dc7f37aa13f0d77f2494bda1c8de6b016dd388d29952a4c4672b6ce8"],
+  ["Argon2id", 0x13, "0d640df58d78766c08c037a34a8b53c9d01ef0452d75b65eb52520e96b01e659"],
+  ["Argon2d", 0x10, "96a9d4e5a1734092c85e29f410a45914a5dd1f5cbf08b2670da68a0285abf32b"],
+  ["Argon2i", 0x10, "87aeedd6517ab830cd9765cd8231abb2e647a5dee08f7c05e02fcb763335d0fd"],
+  ["Argon2id", 0x10, "b64615f07789b66b645b67ee9ed3b377ae350b6bfcbb0fc95141ea8f322613c0"],
+];
+
+for (const [algorithm, version, tag] of REFERENCE_VECTORS) {
+  Deno.test({
+    name: `Reference vector ${algorithm} 0x${version.toString(16)}`,
+    fn: () => {
+      const raw = hashRaw(new Uint8Array(32).fill(0x01), new Uint8Array(16).fill(0x02), 32, {
+        algorithm,
+        version,
+        mCost: 32,
+        tCost: 3,
+        pCost: 4,
+        secret: new Uint8Array(8).fill(0x03).buffer,
+        data: new Uint8Array(12).fill(0x04),
+      });
+      assertEquals(encodeHex(raw), tag);
+    },
+  });
+}
+
+Deno.test({
+  name: "Associated data changes the raw hash",
+  fn: () => {
+    const params: Argon2Params = { algorithm: "Argon2id", version: 0x13, mCost: 64, tCost: 1 };
+    const plain = hashRaw(password, salt, 32, params);
+    const bound = hashRaw(password, salt, 32, { ...params, data: encode("tenant-42") });
+    assert(encodeBase64(plain) !== encodeBase64(bound));
+
+    assertArgon2Error(
+      () => hash(password, salt, { ...params, data: new Uint8Array(33) }),
+      Argon2ErrorCode.InvalidParams,
+    );
+  },
+});
+
+Deno.test({
+  name: "Salts longer than 48 bytes",
+  fn: () => {
+    const params: Argon2Params = { algorithm: "Argon2id", version: 0x13, mCost: 64, tCost: 1 };
+    const longSalt = new Uint8Array(64).map((_, i) => i);
+
+    const digest = hash(password, longSalt, params);
+    const [, , , , encodedSalt, tag] = digest.split("$");
+    assertEquals(encodedSalt, encodeBase64(longSalt).replace(/=+$/, ""));
+    assertEquals(tag, encodeBase64(hashRaw(password, longSalt, 32, params)).replace(/=+$/, ""));
+
+    assert(verify(digest, password));
+    assert(!verify(digest, password2));
+
+    assertArgon2Error(
+      () => hash(password, encode("7 bytes"), params),
+      Argon2ErrorCode.SaltTooShort,
+    );
+  },
+});
+
+Deno.test({
+  name: "Needs rehash when below the target parameters",
+  fn: () => {
+    const [params, digest] = TESTS[0]; // m=65536,t=2,p=1, 16 byte salt
+
+    assert(!needsRehash(digest, { algorithm: "Argon2id", version: 0x13, mCost: 65536 }));
+    assert(!needsRehash(digest, { algorithm: "Argon2id", version: 0x13, mCost: 19456 }));
+    assert(needsRehash(digest, { ...params, mCost: 131072 }));
+    assert(needsRehash(digest, { algorithm: "Argon2id", version: 0x13, mCost: 65536, tCost: 3 }));
+    assert(needsRehash(digest, { algorithm: "Argon2id", version: 0x13, mCost: 65536, pCost: 2 }));
+    assert(needsRehash(digest, { algorithm: "Argon2id", version: 0x13, mCost: 65536, outputLen: 64 }));
+    assert(needsRehash(digest, { algorithm: "Argon2i", version: 0x13, mCost: 65536 }));
+    assert(needsRehash(digest, { algorithm: "Argon2id", version: 0x13, mCost: 65536 }, 32));
+
+    assertArgon2Error(
+      () => needsRehash("not a digest"),
+      Argon2ErrorCode.MalformedDigest,
+    );
+  },
+});
+
+Deno.test({
+  name: "Verify and upgrade",
+  fn: () => {
+    const [, digest] = TESTS[0]; // m=65536,t=2,p=1
+    const freshSalt = encode("a fresher salt!!");
+    const target: Argon2Params = { algorithm: "Argon2id", version: 0x13, mCost: 65536, tCost: 3 };
+
+    assertEquals(verifyAndUpgrade(digest, password2, freshSalt, { ...target }), { matches: false });
+    assertEquals(
+      verifyAndUpgrade(digest, password, freshSalt, { algorithm: "Argon2id", version: 0x13, mCost: 65536 }),
+      { matches: true },
+    );
+
+    const upgraded = verifyAndUpgrade(digest, password, freshSalt, { ...target });
+    assert(upgraded.matches);
+    assertEquals(upgraded.digest, hash(password, freshSalt, { ...target }));
+    assert(!needsRehash(upgraded.digest!, { ...target }));
+  },
+});
+
+Deno.test({
+  name: "Verify policy rejects over-budget digests",
+  fn: () => {
+    const [, digest] = TESTS[0]; // argon2id, m=65536,t=2,p=1
+    const expensive = "$argon2id$v=19$m=4194304,t=1000,p=1$eGVub24yJ3Mgc28gY29vbA$l2g9IkHxa2w5HAL0YuofExQCjELI/9wyYkmrNHhoa28";
+
+    try {
+      setVerifyPolicy({ maxMCost: 65536, maxTCost: 3, maxPCost: 1 });
+      assert(verify(digest, password));
+      assertArgon2Error(() => verify(expensive, password), Argon2ErrorCode.PolicyViolation);
+
+      setVerifyPolicy({ algorithms: ["Argon2i"] });
+      assertArgon2Error(() => verify(digest, password), Argon2ErrorCode.PolicyViolation);
+
+      setVerifyPolicy({ versions: [0x10] });
+      assertArgon2Error(() => verify(digest, password), Argon2ErrorCode.PolicyViolation);
+    } finally {
+      setVerifyPolicy({});
+    }
+  },
+});
+
+Deno.test({
+  name: "Contexts reuse their memory across calls",
+  fn: () => {
+    const [params, digest] = TESTS[0];
+    const context = createContext(params);
+
+    try {
+      for (let i = 0; i < 3; i++) {
+        assertEquals(context.hash(password, salt), digest);
+        assert(context.verify(digest, password));
+        assert(!context.verify(digest, password2));
+      }
+
+      // Digests with other parameters still verify
+      const [, smaller] = TESTS[1];
+      assert(context.verify(smaller, password));
+    } finally {
+      context.free();
+    }
+
+    assertArgon2Error(() => context.free(), Argon2ErrorCode.InvalidHandle);
+  },
+});
+
+Deno.test({
+  name: "Memory stays bounded across hash/verify cycles",
+  fn: async () => {
+    // A fresh instance, so other tests don't affect its memory
+    const wasm = await wasmBuilder(WebAssembly);
+
+    const [, digest] = TESTS[1]; // m=19456,t=2,p=1
+    const algorithm = new DataView(encode("id__").buffer).getUint32(0, true);
+    const [digestPtr, digestLen] = write(wasm, encode(digest));
+    const [passwordPtr, passwordLen] = write(wasm, password);
+    const [saltPtr, saltLen] = write(wasm, salt);
+    const outputPtr = wasm.alloc(32);
+    const matchesPtr = wasm.alloc(4);
+
+    const cycle = () => {
+      assertEquals(
+        wasm.hashRaw(passwordPtr, passwordLen, saltPtr, saltLen, 0, 0, 0, 0, algorithm, 0x13, 19456, 2, 1, outputPtr, 32),
+        0,
+      );
+      assertEquals(
+        wasm.verify(digestPtr, digestLen, passwordPtr, passwordLen, 0, 0, matchesPtr),
+        0,
+      );
+    };
+
+    cycle();
+    const size = wasm.memory.buffer.byteLength;
+    for (let i = 0; i < 50; i++) {
+      cycle();
+    }
+    assertEquals(wasm.memory.buffer.byteLength, size);
+  },
+});
+
+Deno.test({
+  name: "Memory stats return to their baseline after a hash",
+  fn: () => {
+    const [params, digest] = TESTS[1]; // m=19456,t=2,p=1
+    const before = memoryStats();
+
+    assertEquals(hash(password, salt, params), digest);
+
+    const after = memoryStats();
+    assertEquals(after.allocatedBytes, before.allocatedBytes);
+    assertEquals(after.liveAllocations, before.liveAllocations);
+    assert(after.peakBytes >= 19456 * 1024);
+    assert(after.pages * 65536 >= after.peakBytes);
+  },
+});
+
+Deno.test({
+  name: "Exceeding the memory limit fails without breaking the instance",
+  fn: () => {
+    const [params, digest] = TESTS[1]; // m=19456,t=2,p=1
+    const before = memoryStats();
+
+    setMemoryLimit(before.allocatedBytes + 8 * 1024 * 1024);
+    try {
+      assertArgon2Error(() => hash(password, salt, params), Argon2ErrorCode.OutOfMemory);
+      assertArgon2Error(() => verify(digest, password), Argon2ErrorCode.OutOfMemory);
+      // Inputs are checked before memory is set aside for the blocks
+      assertArgon2Error(() => hash(password, encode("salt"), params), Argon2ErrorCode.SaltTooShort);
+      assertArgon2Error(() => beginHash(password, encode("salt"), params), Argon2ErrorCode.SaltTooShort);
+    } finally {
+      setMemoryLimit();
+    }
+
+    assertEquals(hash(password, salt, params), digest);
+    assert(verify(digest, password));
+
+    // Limits past what 32-bit memory can address are clamped, not truncated
+    setMemoryLimit(8 * 1024 ** 3);
+    try {
+      assertEquals(hash(password, salt, params), digest);
+    } finally {
+      setMemoryLimit();
+    }
+    assertThrows(() => setMemoryLimit(-1), RangeError);
+    assertThrows(() => setMemoryLimit(NaN), RangeError);
+    assertThrows(() => setMemoryLimit(Infinity), RangeError);
+  },
+});
+
+Deno.test({
+  name: "dealloc_zeroize wipes freed buffers",
+  fn: async () => {
+    const wasm = await wasmBuilder(WebAssembly);
+    const secret = encode("correct horse battery staple ".repeat(8));
+    const contains = (ptr: number) =>
+      new TextDecoder().decode(new Uint8Array(wasm.memory.buffer, ptr, secret.length)).includes("battery staple");
+
+    const kept = wasm.alloc(secret.length);
+    new Uint8Array(wasm.memory.buffer, kept, secret.length).set(secret);
+    const wiped = wasm.alloc(secret.length);
+    new Uint8Array(wasm.memory.buffer, wiped, secret.length).set(secret);
+
+    wasm.deallocZeroize(wiped, secret.length);
+    assert(!contains(wiped));
+    assert(contains(kept));
+    wasm.dealloc(kept, secret.length);
+  },
+});
+
+Deno.test({
+  name: "Reset recovers an instance after a trap",
+  fn: async () => {
+    const wasm = await wasmBuilder(WebAssembly);
+    const allocated = () => new DataView(wasm.memory.buffer, wasm.memoryStats(), 16).getUint32(0, true);
+
+    const algorithm = new DataView(encode("id__").buffer).getUint32(0, true);
+    const [passwordPtr, passwordLen] = write(wasm, password);
+    const [saltPtr, saltLen] = write(wasm, salt);
+    const outputLocPtr = wasm.alloc(4);
+    const hashSmall = () =>
+      wasm.hashWithParams(passwordPtr, passwordLen, saltPtr, saltLen, 0, 0, 0, 0, algorithm, 0x13, 8, 1, 1, 32, outputLocPtr);
+
+    // Leave room for the tag and Argon2's 8 blocks, but not the digest, so an
+    // infallible allocation fails after hashing
+    wasm.setMemoryLimit(allocated() + 32 + 8 * 1024);
+    assertThrows(hashSmall, Error, "Memory allocation");
+    assertEquals(wasm.isPoisoned(), 1);
+
+    wasm.setMemoryLimit(0xFFFFFFFF);
+    wasm.reset();
+    assertEquals(wasm.isPoisoned(), 0);
+    assertEquals(allocated(), 0);
+
+    // Pointers from before the reset are invalid, so start over
+    const [newPasswordPtr, newPasswordLen] = write(wasm, password);
+    const [newSaltPtr, newSaltLen] = write(wasm, salt);
+    const newOutputLocPtr = wasm.alloc(4);
+    assertEquals(
+      wasm.hashWithParams(newPasswordPtr, newPasswordLen, newSaltPtr, newSaltLen, 0, 0, 0, 0, algorithm, 0x13, 8, 1, 1, 32, newOutputLocPtr),
+      0,
+    );
+  },
+});
+
+Deno.test({
+  name: "Hashing can be cancelled between slices",
+  fn: () => {
+    const [params, digest] = TESTS[1]; // t=2, so 8 chances to cancel
+
+    let polls = 0;
+    const cancelAtThirdPoll = () =>
+      assertArgon2Error(
+        () => hash(password, salt, { ...params, shouldCancel: () => ++polls % 3 === 0 }),
+        Argon2ErrorCode.Cancelled,
+      );
+    cancelAtThirdPoll();
+    assertEquals(polls, 3);
+
+    // Cancelling frees everything but the error message, now the same size
+    const before = memoryStats();
+    cancelAtThirdPoll();
+    assertEquals(memoryStats().allocatedBytes, before.allocatedBytes);
+
+    assertArgon2Error(
+      () => verify(digest, password, undefined, { shouldCancel: () => true }),
+      Argon2ErrorCode.Cancelled,
+    );
+    assertThrows(
+      () =>
+        hash(password, salt, {
+          ...params,
+          shouldCancel: () => {
+            throw new RangeError("deadline passed");
+          },
+        }),
+      RangeError,
+      "deadline passed",
+    );
+
+    polls = 0;
+    assertEquals(hash(password, salt, { ...params, shouldCancel: () => (polls++, false) }), digest);
+    assertEquals(polls, 8);
+  },
+});
+
+Deno.test({
+  name: "Progress is reported after every segment",
+  fn: () => {
+    const [, digest] = TESTS[1];
+    const params: Argon2Params = { algorithm: "Argon2id", version: 0x13, outputLen: 16, pCost: 2 };
+    const reports: Argon2Progress[] = [];
+
+    hash(password, salt, { ...params, onProgress: (progress) => reports.push(progress) });
+
+    assertEquals(reports.length, 2 * 4 * 2); // passes * slices * lanes
+    assertEquals(reports[0], { pass: 0, slice: 0, lane: 0, fraction: reports[0].fraction });
+    assertEquals(reports.at(-1), { pass: 1, slice: 3, lane: 1, fraction: 1 });
+    for (let i = 1; i < reports.length; i++) {
+      assert(reports[i].fraction > reports[i - 1].fraction);
+    }
+
+    reports.length = 0;
+    hashRaw(password, salt, 32, { ...params, onProgress: (progress) => reports.push(progress) });
+    assertEquals(reports.length, 16);
+
+    assertThrows(
+      () =>
+        verify(digest, password, undefined, {
+          onProgress: () => {
+            throw new Error("stop");
+          },
+        }),
+      Error,
+      "stop",
+    );
+  },
+});
+
+Deno.test({
+  name: "Hash jobs run step by step and match one-shot hashing",
+  fn: () => {
+    const [params, digest] = TESTS[3];
+    const [rawParams] = TESTS[1];
+
+    const job = beginHash(password, salt, params);
+    const rawJob = beginHash(password, salt, rawParams);
+    assertArgon2Error(() => job.finish(), Argon2ErrorCode.Unfinished);
+
+    let steps = 0;
+    while (!job.step(1000)) {
+      steps++;
+      rawJob.step(1000);
+    }
+    assertEquals(steps, Math.ceil((2 * 19456 - 2) / 1000) - 1); // the first two blocks are computed up front
+    assertEquals(job.finish(), digest);
+    assertArgon2Error(() => job.step(), Argon2ErrorCode.InvalidHandle);
+
+    assert(rawJob.step());
+    assertEquals(rawJob.finishRaw(), hashRaw(password, salt, 16, rawParams));
+
+    // Aborting frees the job's memory
+    const before = memoryStats();
+    beginHash(password, salt, params).abort();
+    assertEquals(memoryStats().allocatedBytes, before.allocatedBytes);
+    assertArgon2Error(() => job.finish(), Argon2ErrorCode.InvalidHandle);
+  },
+});
+
+Deno.test({
+  name: "SIMD and scalar builds produce identical digests",
+  fn: async () => {
+    const scalar = await buildWithRuntime(WebAssembly, { simd: false });
+    const simd = await buildWithRuntime(WebAssembly, { simd: true });
+
+    for (const [params, digest] of TESTS) {
+      assertEquals(scalar.hash(password, salt, params), digest);
+      assertEquals(simd.hash(password, salt, params), digest);
+    }
+
+    const params: Argon2Params = { algorithm: "Argon2d", version: 0x10, mCost: 64, pCost: 4 };
+    assertEquals(simd.hashRaw(password, salt, 64, params), scalar.hashRaw(password, salt, 64, params));
+  },
+});
+
+Deno.test({
+  name: "Capabilities describe the loaded build",
+  fn: async () => {
+    const reported = capabilities();
+    assertEquals(reported.abiVersion, 1);
+    assertEquals(reported.algorithms, ["Argon2d", "Argon2i", "Argon2id"]);
+    assertEquals(reported.versions, [0x10, 0x13]);
+    assert(reported.features.secret && reported.features.associatedData && reported.features.rawOutput);
+    assert(!reported.features.threads && !reported.features.memory64 && !reported.features.scratch);
+    assertEquals(reported.limits.minSaltLen, 8);
+    assertEquals(reported.limits.maxDataLen, 32);
+    // 32-bit memory holds fewer blocks than Argon2 allows
+    assertEquals(reported.limits.maxMCost, 2 ** 22 - 1);
+
+    const scalar = await buildWithRuntime(WebAssembly, { simd: false });
+    const simd = await buildWithRuntime(WebAssembly, { simd: true });
+    assertEquals(scalar.capabilities().features.simd, false);
+    assertEquals(simd.capabilities().features.simd, true);
+  },
+});
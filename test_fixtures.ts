@@ -0,0 +1,28 @@
+// Inputs and expected digests shared by test.ts and variants_test.ts
+import type { Argon2Params } from "./mod_runtime_agnostic.ts";
+
+const encoder = new TextEncoder();
+export const encode = (str: string) => encoder.encode(str);
+
+export const password = encode("here's a very cool password");
+export const password2 = encode("here's a different, less-cool password");
+export const salt = encode("xenon2's so cool");
+
+export const TESTS: [Argon2Params, string][] = [
+  [
+    { algorithm: "Argon2id", version: 0x13, mCost: 65536 },
+    "$argon2id$v=19$m=65536,t=2,p=1$eGVub24yJ3Mgc28gY29vbA$l2g9IkHxa2w5HAL0YuofExQCjELI/9wyYkmrNHhoa28",
+  ],
+  [
+    { algorithm: "Argon2id", version: 0x13, outputLen: 16 },
+    "$argon2id$v=19$m=19456,t=2,p=1$eGVub24yJ3Mgc28gY29vbA$7cO4tpeUkL5aKQTTKpesnQ",
+  ],
+  [
+    { algorithm: "Argon2id", version: 0x13, outputLen: 64 },
+    "$argon2id$v=19$m=19456,t=2,p=1$eGVub24yJ3Mgc28gY29vbA$Ew/DrMbtuqKgDmpZdDwF1tN2dF07c/Oqlck/rsfXUM09axiXv65W1bRnKDBwrPk/EYj0JLR9j4lsIzZdP2iQ6A",
+  ],
+  [
+    { algorithm: "Argon2id", version: 0x13, data: encode("tenant-42") },
+    "$argon2id$v=19$m=19456,t=2,p=1,data=dGVuYW50LTQy$eGVub24yJ3Mgc28gY29vbA$eWauw5vRHiTKXWX4Y4OUfcmTZFixLBre6lkympMhvX4",
+  ],
+];
@@ -0,0 +1,144 @@
+import { assert, assertEquals, assertThrows } from "jsr:@std/assert@0.221";
+import { encodeHex } from "@std/encoding/hex";
+
+import type { Argon2Params } from "./mod_runtime_agnostic.ts";
+import { password, password2, salt, TESTS } from "./test_fixtures.ts";
+
+/**
+ * Whether `deno task build:<variant>` has generated the variant's module.
+ * Those builds need a nightly toolchain, so tests of a missing one are skipped;
+ * CI builds them in the `variants` job.
+ */
+async function isBuilt(variant: string): Promise<boolean> {
+  try {
+    await Deno.stat(new URL(`./wasm/wasm_${variant}.js`, import.meta.url));
+    return true;
+  } catch {
+    return false;
+  }
+}
+
+// Lanes only run in parallel when pCost is above 1. Expected outputs are those
+// of the single-threaded build.
+const LANES_PARAMS: Argon2Params = { algorithm: "Argon2id", version: 0x13, mCost: 4096, pCost: 4 };
+const LANES_DIGEST = "$argon2id$v=19$m=4096,t=2,p=4$eGVub24yJ3Mgc28gY29vbA$VyC6bhjCbygGI3XU05dANCIPHgEBmODLo/YSHLA8RQQ";
+const LANES_RAW = "99d1c75d1cee7ded454b888b79e4b329fdb7c0c4c2bed858c5bd36fee73ea673" +
+  "cb1d521045245aaecd6c5e6c6597ccf780328bd166de6f1a3c43055199c9674c";
+
+Deno.test({
+  name: "Threaded build produces the single-threaded digests",
+  ignore: !await isBuilt("threads"),
+  fn: async () => {
+    const threads = await import("./mod_threads.ts");
+
+    try {
+      for (const [params, digest] of TESTS) {
+        assertEquals(threads.hash(password, salt, params), digest);
+        assert(threads.verify(digest, password));
+      }
+      assertEquals(threads.hash(password, salt, LANES_PARAMS), LANES_DIGEST);
+      assertEquals(encodeHex(threads.hashRaw(password, salt, 64, LANES_PARAMS)), LANES_RAW);
+    } finally {
+      threads.terminate();
+    }
+
+    // Lanes are computed on the hashing thread once the workers are gone
+    assertEquals(threads.hash(password, salt, LANES_PARAMS), LANES_DIGEST);
+  },
+});
+
+Deno.test({
+  name: "Atomics build produces the same digests from instances sharing memory",
+  ignore: !await isBuilt("atomics"),
+  fn: async () => {
+    const { compile, instantiate } = await import("./mod_atomics.ts");
+    const shared = await compile();
+    const first = await instantiate(shared);
+    const second = await instantiate(shared);
+    assert(first.capabilities().features.atomics);
+
+    for (const [params, digest] of TESTS) {
+      assertEquals(first.hash(password, salt, params), digest);
+      assert(second.verify(digest, password));
+      assertEquals(second.hash(password, salt, params), digest);
+    }
+  },
+});
+
+// The smallest module with a 64-bit memory. Older runtimes only accept it
+// behind a V8 flag.
+const supportsMemory64 = WebAssembly.validate(
+  new Uint8Array([0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x05, 0x03, 0x01, 0x04, 0x00]),
+);
+
+Deno.test({
+  name: "Memory64 build produces the reference digests",
+  ignore: !supportsMemory64 || !await isBuilt("memory64"),
+  fn: async () => {
+    const memory64 = await import("./mod_memory64.ts");
+    assert(memory64.capabilities().features.memory64);
+
+    for (const [params, digest] of TESTS) {
+      assertEquals(memory64.hash(password, salt, params), digest);
+      assert(memory64.verify(digest, password));
+      assert(!memory64.verify(digest, password2));
+    }
+
+    // Pointers, sizes and out-parameters cross the boundary as 64-bit values
+    const [params, digest] = TESTS[0];
+    assertEquals(
+      encodeHex(memory64.hashRaw(password, salt, 64, params)),
+      "8d8170d9e24db884e36b02034d4c7c957e91b9f37f478fa5394a4b1a8a54edd3" +
+        "0b344c86ad812ef6fe505f3a7c740097d47eca030ee8c357b87cb2f7abb02955",
+    );
+    const error = assertThrows(() => memory64.verify("not a digest", password), memory64.Argon2Error);
+    assert(error.message.startsWith("Invalid digest format"));
+
+    const context = memory64.createContext(params);
+    try {
+      assertEquals(context.hash(password, salt), digest);
+    } finally {
+      context.free();
+    }
+    const job = memory64.beginHash(password, salt, params);
+    assert(job.step());
+    assertEquals(job.finish(), digest);
+
+    const stats = memory64.memoryStats();
+    assert(stats.pages > 0 && stats.peakBytes >= stats.allocatedBytes);
+  },
+});
+
+Deno.test({
+  name: "Scratch build produces the reference digests outside linear memory",
+  ignore: !await isBuilt("scratch"),
+  fn: async () => {
+    const scratch = await import("./mod_scratch.ts");
+    assert(scratch.capabilities().features.scratch);
+
+    const before = scratch.memoryStats();
+    for (const [params, digest] of TESTS) {
+      assertEquals(scratch.hash(password, salt, params), digest);
+      assert(scratch.verify(digest, password));
+    }
+
+    // The 64 MiB of blocks of m=65536 are counted but never in linear memory
+    const blockBytes = 65536 * 1024;
+    const after = scratch.memoryStats();
+    assert(after.peakBytes >= blockBytes);
+    assertEquals(after.allocatedBytes, before.allocatedBytes);
+    assert(after.pages * 65536 < blockBytes);
+
+    // A context keeps its buffer between hashes
+    const [params, digest] = TESTS[0];
+    const context = scratch.createContext(params);
+    try {
+      assert(scratch.memoryStats().allocatedBytes >= before.allocatedBytes + blockBytes);
+      assertEquals(context.hash(password, salt), digest);
+      assertEquals(context.hash(password, salt), digest);
+    } finally {
+      context.free();
+    }
+    assertEquals(scratch.memoryStats().allocatedBytes, before.allocatedBytes);
+  },
+});
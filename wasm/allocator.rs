@@ -0,0 +1,225 @@
+//! The global allocator, selected by cargo feature, and instrumentation used
+//! to report memory statistics and enforce the memory limit.
+//!
+//! `dlmalloc` (the default) coalesces freed chunks and reuses them, so the
+//! large block buffers Argon2 allocates on every call are returned to the heap
+//! instead of fragmenting it. `wee_alloc` is kept for hosts that depend on its
+//! smaller code size, but is unmaintained and leaks under that pattern.
+
+use crate::error::{Error, Failure, Result};
+use core::alloc::{GlobalAlloc, Layout};
+use core::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+
+#[cfg(feature = "dlmalloc")]
+#[global_allocator]
+static ALLOC: Instrumented<crate::heap::Heap> =
+  Instrumented::new(crate::heap::Heap::new());
+
+#[cfg(all(feature = "wee_alloc", not(feature = "dlmalloc")))]
+#[global_allocator]
+static ALLOC: Instrumented<wee_alloc::WeeAlloc> =
+  Instrumented::new(wee_alloc::WeeAlloc::INIT);
+
+#[cfg(not(any(feature = "dlmalloc", feature = "wee_alloc")))]
+compile_error!("enable one of the `dlmalloc` or `wee_alloc` features");
+
+#[cfg(all(feature = "dlmalloc", feature = "wee_alloc"))]
+compile_error!(
+  "the `dlmalloc` and `wee_alloc` features can't be combined; build \
+   `wee_alloc` with `--no-default-features`"
+);
+
+/// Wraps an allocator, counting the bytes and allocations it hands out and
+/// failing allocations that would take it past `limit` bytes. Bytes are
+/// counted before allocating, so threads allocating at once can't together
+/// exceed the limit.
+pub struct Instrumented<A> {
+  inner: A,
+  allocated: AtomicUsize,
+  peak: AtomicUsize,
+  allocations: AtomicUsize,
+  limit: AtomicUsize,
+}
+
+impl<A> Instrumented<A> {
+  const fn new(inner: A) -> Self {
+    Instrumented {
+      inner,
+      allocated: AtomicUsize::new(0),
+      peak: AtomicUsize::new(0),
+      allocations: AtomicUsize::new(0),
+      limit: AtomicUsize::new(usize::MAX),
+    }
+  }
+
+  /// Bytes that can still be allocated before reaching the limit.
+  fn available(&self) -> usize {
+    let allocated = self.allocated.load(Relaxed);
+    self.limit.load(Relaxed).saturating_sub(allocated)
+  }
+
+  /// Counts `size` more bytes as allocated unless that would exceed the
+  /// limit, returning whether they were counted.
+  fn try_count(&self, size: usize) -> bool {
+    let limit = self.limit.load(Relaxed);
+    let counted = self.allocated.fetch_update(Relaxed, Relaxed, |allocated| {
+      allocated
+        .checked_add(size)
+        .filter(|&allocated| allocated <= limit)
+    });
+
+    match counted {
+      Ok(allocated) => {
+        self.peak.fetch_max(allocated + size, Relaxed);
+        true
+      }
+      Err(_) => false,
+    }
+  }
+
+  fn uncount(&self, size: usize) {
+    self.allocated.fetch_sub(size, Relaxed);
+  }
+
+  /// Counts the allocation `alloc` makes of `size` bytes, if there is room.
+  unsafe fn counted(
+    &self,
+    size: usize,
+    alloc: impl FnOnce() -> *mut u8,
+  ) -> *mut u8 {
+    if !self.try_count(size) {
+      return core::ptr::null_mut();
+    }
+
+    let ptr = alloc();
+    if ptr.is_null() {
+      self.uncount(size);
+    } else {
+      self.allocations.fetch_add(1, Relaxed);
+    }
+    ptr
+  }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for Instrumented<A> {
+  unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+    self.counted(layout.size(), || self.inner.alloc(layout))
+  }
+
+  unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    self.inner.dealloc(ptr, layout);
+    self.uncount(layout.size());
+    self.allocations.fetch_sub(1, Relaxed);
+  }
+
+  unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+    self.counted(layout.size(), || self.inner.alloc_zeroed(layout))
+  }
+
+  unsafe fn realloc(
+    &self,
+    ptr: *mut u8,
+    layout: Layout,
+    new_size: usize,
+  ) -> *mut u8 {
+    let old_size = layout.size();
+    let grown = new_size.saturating_sub(old_size);
+    if !self.try_count(grown) {
+      return core::ptr::null_mut();
+    }
+
+    let new_ptr = self.inner.realloc(ptr, layout, new_size);
+    if new_ptr.is_null() {
+      self.uncount(grown);
+    } else {
+      self.uncount(old_size.saturating_sub(new_size));
+    }
+    new_ptr
+  }
+}
+
+/// Snapshot of the allocator's counters, laid out for the host to read.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MemoryStats {
+  /// Bytes currently allocated.
+  pub allocated: usize,
+  /// Highest value `allocated` has reached.
+  pub peak: usize,
+  /// Allocations not yet freed.
+  pub allocations: usize,
+  /// Size of linear memory in 64 KiB wasm pages.
+  pub pages: usize,
+}
+
+pub fn memory_stats() -> MemoryStats {
+  #[cfg(any(target_arch = "wasm32", target_arch = "wasm64"))]
+  let pages = crate::arch::memory_size(0);
+  #[cfg(not(any(target_arch = "wasm32", target_arch = "wasm64")))]
+  let pages = 0;
+
+  MemoryStats {
+    allocated: ALLOC.allocated.load(Relaxed),
+    peak: ALLOC.peak.load(Relaxed),
+    allocations: ALLOC.allocations.load(Relaxed),
+    pages,
+  }
+}
+
+/// Frees every allocation at once by resetting the heap, returning whether the
+/// selected allocator supports it. Only `dlmalloc` does, and not in the
+/// `atomics` build, where other threads may still be using the heap.
+pub unsafe fn reset_heap() -> bool {
+  #[cfg(all(feature = "dlmalloc", not(feature = "atomics")))]
+  {
+    ALLOC.inner.reset();
+    ALLOC.allocated.store(0, Relaxed);
+    ALLOC.allocations.store(0, Relaxed);
+    true
+  }
+  #[cfg(not(all(feature = "dlmalloc", not(feature = "atomics"))))]
+  false
+}
+
+/// Sets the most bytes the module may have allocated at once. Allocations past
+/// it fail as if linear memory could not grow.
+pub fn set_memory_limit(limit: usize) {
+  ALLOC.limit.store(limit, Relaxed);
+}
+
+/// Checks that `bytes` more can be allocated without exceeding the limit, so
+/// large allocations can fail with [`Error::OutOfMemory`] instead of
+/// reaching the allocation error handler.
+#[cfg(not(feature = "scratch"))]
+pub fn reserve(bytes: usize) -> Result<()> {
+  let available = ALLOC.available();
+  if bytes > available {
+    return Err(limit_exceeded(bytes, available));
+  }
+  Ok(())
+}
+
+/// Counts `bytes` held outside linear memory, such as the scratch buffers of
+/// the `scratch` build, as allocated, failing like [`reserve`] if that would
+/// exceed the limit. They are given back with [`release`].
+#[cfg(feature = "scratch")]
+pub fn claim(bytes: usize) -> Result<()> {
+  if !ALLOC.try_count(bytes) {
+    return Err(limit_exceeded(bytes, ALLOC.available()));
+  }
+  Ok(())
+}
+
+/// Stops counting `bytes` counted by [`claim`].
+#[cfg(feature = "scratch")]
+pub fn release(bytes: usize) {
+  ALLOC.uncount(bytes);
+}
+
+fn limit_exceeded(bytes: usize, available: usize) -> Failure {
+  let limit = ALLOC.limit.load(Relaxed);
+  Error::OutOfMemory.with(format_args!(
+    "Memory limit exceeded: {bytes} bytes needed, \
+     {available} of {limit} available"
+  ))
+}
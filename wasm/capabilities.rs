@@ -0,0 +1,101 @@
+//! What a build of the module supports, so hosts can tell builds apart and
+//! only rely on what the one they loaded provides.
+
+use crate::engine::Block;
+use crate::policy::VerifyPolicy;
+
+/// Version of the exports' signatures and of the layout of what they read and
+/// write through pointers. Incremented on incompatible changes only; exports
+/// and capabilities are added without one.
+pub const ABI_VERSION: u32 = 1;
+
+/// Bits of [`Capabilities::features`].
+pub mod feature {
+  /// Hashes may be keyed with a secret.
+  pub const SECRET: u32 = 1 << 0;
+  /// Hashes may be bound to associated data.
+  pub const ASSOCIATED_DATA: u32 = 1 << 1;
+  /// `hash_raw` and `hash_finish_raw` return the bare tag.
+  pub const RAW_OUTPUT: u32 = 1 << 2;
+  /// Lanes are computed on worker threads, see `wasm/threads.rs`.
+  pub const THREADS: u32 = 1 << 3;
+  /// The compression function uses wasm SIMD.
+  pub const SIMD: u32 = 1 << 4;
+  /// Instances on several threads may share one memory.
+  pub const ATOMICS: u32 = 1 << 5;
+  /// Pointers and sizes are 64-bit.
+  pub const MEMORY64: u32 = 1 << 6;
+  /// Argon2 blocks are kept in scratch buffers, see `wasm/matrix.rs`.
+  pub const SCRATCH: u32 = 1 << 7;
+}
+
+const fn flag(enabled: bool, bit: u32) -> u32 {
+  if enabled {
+    bit
+  } else {
+    0
+  }
+}
+
+/// Description of this build returned by `capabilities`. Every field is a
+/// `u32`, whatever the pointer size, and fields are only ever appended.
+#[repr(C)]
+pub struct Capabilities {
+  /// Supported algorithms, encoded like [`VerifyPolicy::algorithms`].
+  pub algorithms: u32,
+  /// Supported versions, encoded like [`VerifyPolicy::versions`].
+  pub versions: u32,
+  /// Bit set of the [`feature`] constants.
+  pub features: u32,
+  pub min_m_cost: u32,
+  /// Also capped by what the address space can hold.
+  pub max_m_cost: u32,
+  pub min_t_cost: u32,
+  pub max_t_cost: u32,
+  pub min_p_cost: u32,
+  pub max_p_cost: u32,
+  pub min_output_len: u32,
+  pub max_output_len: u32,
+  pub max_password_len: u32,
+  pub min_salt_len: u32,
+  pub max_salt_len: u32,
+  pub max_secret_len: u32,
+  pub max_data_len: u32,
+}
+
+/// The most blocks the address space can hold, in the 32-bit builds fewer
+/// than Argon2 allows.
+const fn max_m_cost() -> u32 {
+  let blocks = usize::MAX / core::mem::size_of::<Block>();
+  if blocks < argon2::Params::MAX_M_COST as usize {
+    blocks as u32
+  } else {
+    argon2::Params::MAX_M_COST
+  }
+}
+
+pub static CAPABILITIES: Capabilities = Capabilities {
+  algorithms: VerifyPolicy::UNRESTRICTED.algorithms,
+  versions: VerifyPolicy::UNRESTRICTED.versions,
+  features: feature::SECRET
+    | feature::ASSOCIATED_DATA
+    | feature::RAW_OUTPUT
+    | flag(cfg!(feature = "threads"), feature::THREADS)
+    | flag(cfg!(target_feature = "simd128"), feature::SIMD)
+    | flag(cfg!(feature = "atomics"), feature::ATOMICS)
+    | flag(cfg!(target_arch = "wasm64"), feature::MEMORY64)
+    | flag(cfg!(feature = "scratch"), feature::SCRATCH),
+  min_m_cost: argon2::Params::MIN_M_COST,
+  max_m_cost: max_m_cost(),
+  min_t_cost: argon2::Params::MIN_T_COST,
+  max_t_cost: argon2::Params::MAX_T_COST,
+  min_p_cost: argon2::Params::MIN_P_COST,
+  max_p_cost: argon2::Params::MAX_P_COST,
+  min_output_len: argon2::Params::MIN_OUTPUT_LEN as u32,
+  max_output_len: argon2::Params::MAX_OUTPUT_LEN as u32,
+  max_password_len: argon2::MAX_PWD_LEN as u32,
+  min_salt_len: argon2::MIN_SALT_LEN as u32,
+  max_salt_len: argon2::MAX_SALT_LEN as u32,
+  max_secret_len: argon2::MAX_SECRET_LEN as u32,
+  max_data_len: argon2::AssociatedData::MAX_LEN as u32,
+};
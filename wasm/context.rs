@@ -0,0 +1,36 @@
+use crate::error::Result;
+use crate::matrix::Matrix;
+use crate::slab::Slab;
+use crate::AllParams;
+use alloc::vec::Vec;
+
+/// Hashing parameters together with Argon2 working memory that is kept
+/// between calls, so repeated hashing doesn't reallocate its blocks.
+pub struct HasherContext {
+  pub params: AllParams,
+  pub blocks: Matrix,
+}
+
+#[cfg_attr(feature = "atomics", thread_local)]
+static mut CONTEXTS: Slab<HasherContext> = Slab::new("context");
+
+fn contexts() -> &'static mut Slab<HasherContext> {
+  unsafe { &mut *core::ptr::addr_of_mut!(CONTEXTS) }
+}
+
+pub fn insert(context: HasherContext) -> u32 {
+  contexts().insert(context)
+}
+
+/// Removes every context, returning them to the caller to drop or forget.
+pub fn take_all() -> Vec<Option<HasherContext>> {
+  contexts().take_all()
+}
+
+pub fn get(handle: u32) -> Result<&'static mut HasherContext> {
+  contexts().get(handle)
+}
+
+pub fn remove(handle: u32) -> Result<()> {
+  contexts().remove(handle).map(drop)
+}
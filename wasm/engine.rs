@@ -0,0 +1,713 @@
+//! Argon2 (RFC 9106) memory filling.
+//!
+//! This is done here rather than through the `argon2` crate, which fills all
+//! of memory in one call, so that a fill can be observed, cancelled and
+//! resumed between blocks. Tags are identical to the crate's.
+
+use crate::matrix::{Blocks, Matrix};
+use blake2::digest::{self, Digest, VariableOutput};
+use blake2::{Blake2b512, Blake2bVar};
+use core::ops::Range;
+use zeroize::Zeroize;
+
+const SYNC_POINTS: usize = 4;
+const ADDRESSES_IN_BLOCK: usize = 128;
+
+/// A 1 KiB Argon2 memory block.
+#[derive(Clone, Copy)]
+#[repr(align(64))]
+pub struct Block([u64; 128]);
+
+impl Block {
+  pub const SIZE: usize = 1024;
+
+  const ZERO: Block = Block([0; 128]);
+
+  fn from_bytes(bytes: &[u8; Block::SIZE]) -> Self {
+    let mut block = Block::ZERO;
+    for (word, chunk) in block.0.iter_mut().zip(bytes.chunks_exact(8)) {
+      *word = u64::from_le_bytes(chunk.try_into().unwrap());
+    }
+    block
+  }
+
+  fn to_bytes(self) -> [u8; Block::SIZE] {
+    let mut bytes = [0; Block::SIZE];
+    for (chunk, word) in bytes.chunks_exact_mut(8).zip(self.0) {
+      chunk.copy_from_slice(&word.to_le_bytes());
+    }
+    bytes
+  }
+
+  fn xor(&mut self, other: &Block) {
+    for (word, other) in self.0.iter_mut().zip(other.0) {
+      *word ^= other;
+    }
+  }
+
+  /// The compression function G.
+  #[cfg(target_feature = "simd128")]
+  fn compress(x: &Block, y: &Block) -> Block {
+    simd::compress(x, y)
+  }
+
+  /// The compression function G.
+  #[cfg(not(target_feature = "simd128"))]
+  fn compress(x: &Block, y: &Block) -> Block {
+    let mut r = *x;
+    r.xor(y);
+
+    let mut q = r;
+    for row in q.0.chunks_exact_mut(16) {
+      permute(row, |i| i);
+    }
+    for column in 0..8 {
+      permute(&mut q.0, |i| 2 * column + (i / 2) * 16 + i % 2);
+    }
+
+    q.xor(&r);
+    q
+  }
+}
+
+impl Default for Block {
+  fn default() -> Self {
+    Block::ZERO
+  }
+}
+
+impl Zeroize for Block {
+  fn zeroize(&mut self) {
+    self.0.zeroize();
+  }
+}
+
+/// The BlaMka permutation P over the 16 words `words[index(0..16)]`.
+#[cfg(not(target_feature = "simd128"))]
+#[inline(always)]
+fn permute(words: &mut [u64], index: impl Fn(usize) -> usize) {
+  let mut v = [0; 16];
+  for (i, v) in v.iter_mut().enumerate() {
+    *v = words[index(i)];
+  }
+
+  for [a, b, c, d] in [
+    [0, 4, 8, 12],
+    [1, 5, 9, 13],
+    [2, 6, 10, 14],
+    [3, 7, 11, 15],
+    [0, 5, 10, 15],
+    [1, 6, 11, 12],
+    [2, 7, 8, 13],
+    [3, 4, 9, 14],
+  ] {
+    v[a] = blamka(v[a], v[b]);
+    v[d] = (v[d] ^ v[a]).rotate_right(32);
+    v[c] = blamka(v[c], v[d]);
+    v[b] = (v[b] ^ v[c]).rotate_right(24);
+    v[a] = blamka(v[a], v[b]);
+    v[d] = (v[d] ^ v[a]).rotate_right(16);
+    v[c] = blamka(v[c], v[d]);
+    v[b] = (v[b] ^ v[c]).rotate_right(63);
+  }
+
+  for (i, v) in v.iter().enumerate() {
+    words[index(i)] = *v;
+  }
+}
+
+#[cfg(not(target_feature = "simd128"))]
+#[inline(always)]
+fn blamka(x: u64, y: u64) -> u64 {
+  let product = (x & 0xFFFFFFFF).wrapping_mul(y & 0xFFFFFFFF);
+  x.wrapping_add(y).wrapping_add(product.wrapping_mul(2))
+}
+
+/// Where a fill is: the next block to compute is `index` within the segment
+/// of `lane` in `slice` of `pass`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+  pub pass: usize,
+  pub slice: usize,
+  pub lane: usize,
+  pub index: usize,
+}
+
+/// An Argon2 computation in progress over caller-provided memory.
+///
+/// [`Fill::start`] hashes the inputs into the first blocks of each lane,
+/// [`Fill::step`] computes the rest a bounded number at a time and
+/// [`Fill::finish`] derives the tag. The same matrix must be passed to every
+/// call.
+pub struct Fill {
+  algorithm: argon2::Algorithm,
+  version: argon2::Version,
+  passes: usize,
+  lanes: usize,
+  segment_length: usize,
+  output_len: usize,
+  position: Position,
+}
+
+/// Checks the lengths of the password, salt and secret, so that callers can
+/// reject them before setting memory aside for a fill.
+pub fn check_inputs(
+  password: &[u8],
+  salt: &[u8],
+  secret: Option<&[u8]>,
+) -> argon2::Result<()> {
+  if password.len() > argon2::MAX_PWD_LEN {
+    return Err(argon2::Error::PwdTooLong);
+  }
+  if salt.len() < argon2::MIN_SALT_LEN {
+    return Err(argon2::Error::SaltTooShort);
+  }
+  if salt.len() > argon2::MAX_SALT_LEN {
+    return Err(argon2::Error::SaltTooLong);
+  }
+  if secret.map_or(0, <[u8]>::len) > argon2::MAX_SECRET_LEN {
+    return Err(argon2::Error::SecretTooLong);
+  }
+  Ok(())
+}
+
+impl Fill {
+  pub fn start(
+    algorithm: argon2::Algorithm,
+    version: argon2::Version,
+    params: &argon2::Params,
+    password: &[u8],
+    salt: &[u8],
+    secret: Option<&[u8]>,
+    matrix: &mut Matrix,
+  ) -> argon2::Result<Self> {
+    check_inputs(password, salt, secret)?;
+
+    let lanes = params.p_cost() as usize;
+    let block_count = params.block_count();
+    let fill = Fill {
+      algorithm,
+      version,
+      passes: params.t_cost() as usize,
+      lanes,
+      segment_length: block_count / (lanes * SYNC_POINTS),
+      output_len: params
+        .output_len()
+        .unwrap_or(argon2::Params::DEFAULT_OUTPUT_LEN),
+      position: Position {
+        pass: 0,
+        slice: 0,
+        lane: 0,
+        index: first_index(0, 0),
+      },
+    };
+
+    if matrix.len() < block_count {
+      return Err(argon2::Error::MemoryTooLittle);
+    }
+    let blocks = matrix.blocks();
+    let mut initial_hash =
+      fill.initial_hash(params, password, salt, secret.unwrap_or_default());
+
+    // The first two blocks of each lane are H'(H0 || i || lane)
+    for lane in 0..lanes {
+      for i in 0..2 {
+        let mut bytes = [0; Block::SIZE];
+        blake2b_long(
+          &[
+            &initial_hash,
+            &(i as u32).to_le_bytes(),
+            &(lane as u32).to_le_bytes(),
+          ],
+          &mut bytes,
+        )?;
+        let mut block = Block::from_bytes(&bytes);
+        // SAFETY: the matrix holds every block and is borrowed exclusively
+        unsafe { blocks.set(lane * fill.lane_length() + i, &block) };
+        block.zeroize();
+        bytes.zeroize();
+      }
+    }
+    initial_hash.zeroize();
+
+    Ok(fill)
+  }
+
+  /// H0, the hash of the parameters and inputs.
+  fn initial_hash(
+    &self,
+    params: &argon2::Params,
+    password: &[u8],
+    salt: &[u8],
+    secret: &[u8],
+  ) -> [u8; 64] {
+    let mut digest = Blake2b512::new();
+    digest.update(params.p_cost().to_le_bytes());
+    digest.update((self.output_len as u32).to_le_bytes());
+    digest.update(params.m_cost().to_le_bytes());
+    digest.update(params.t_cost().to_le_bytes());
+    digest.update(u32::from(self.version).to_le_bytes());
+    digest.update((self.algorithm as u32).to_le_bytes());
+    for input in [password, salt, secret, params.data()] {
+      digest.update((input.len() as u32).to_le_bytes());
+      digest.update(input);
+    }
+    digest.finalize().into()
+  }
+
+  fn lane_length(&self) -> usize {
+    self.segment_length * SYNC_POINTS
+  }
+
+  fn block_count(&self) -> usize {
+    self.lane_length() * self.lanes
+  }
+
+  /// The blocks of `matrix`, which must be the one the fill was started with.
+  fn blocks(&self, matrix: &mut Matrix) -> Blocks {
+    let blocks = matrix.blocks();
+    assert!(blocks.len() >= self.block_count());
+    blocks
+  }
+
+  pub fn is_done(&self) -> bool {
+    self.position.pass == self.passes
+  }
+
+  /// Fraction of the blocks computed so far, between 0 and 1.
+  pub fn progress(&self) -> f64 {
+    let Position {
+      pass,
+      slice,
+      lane,
+      index,
+    } = self.position;
+    let segments = (pass * SYNC_POINTS + slice) * self.lanes + lane;
+    let total = self.passes * SYNC_POINTS * self.lanes;
+
+    (segments * self.segment_length + index) as f64
+      / (total * self.segment_length) as f64
+  }
+
+  /// Computes up to `budget` blocks, stopping early at the end of the current
+  /// segment, and returns how many were computed.
+  pub fn step(&mut self, matrix: &mut Matrix, budget: usize) -> usize {
+    if self.is_done() {
+      return 0;
+    }
+
+    let Position {
+      pass,
+      slice,
+      lane,
+      index,
+    } = self.position;
+    let end = self.segment_length.min(index.saturating_add(budget));
+    let blocks = self.blocks(matrix);
+    // SAFETY: the matrix is borrowed exclusively for the call
+    unsafe { self.fill_segment(blocks, pass, slice, lane, index..end) };
+
+    self.position.index = end;
+    if end == self.segment_length {
+      self.next_segment();
+    }
+    end - index
+  }
+
+  /// Computes the current slice, which [`Fill::step`] must not have started.
+  /// `fill_lanes` is given the slice to compute each lane of, possibly on
+  /// several threads, after which `on_segment` is called with the position of
+  /// each segment in turn, as if [`Fill::step`] had computed them one by one.
+  pub fn step_slice(
+    &mut self,
+    matrix: &mut Matrix,
+    fill_lanes: impl FnOnce(&Slice),
+    mut on_segment: impl FnMut(&Self, Position),
+  ) {
+    let Position {
+      pass,
+      slice,
+      lane,
+      index,
+    } = self.position;
+    assert!(!self.is_done() && lane == 0 && index == first_index(pass, slice));
+
+    fill_lanes(&Slice {
+      fill: self,
+      blocks: self.blocks(matrix),
+      pass,
+      slice,
+    });
+
+    for _ in 0..self.lanes {
+      let done = self.position;
+      self.next_segment();
+      on_segment(self, done);
+    }
+  }
+
+  fn next_segment(&mut self) {
+    let position = &mut self.position;
+
+    position.lane += 1;
+    if position.lane == self.lanes {
+      position.lane = 0;
+      position.slice += 1;
+    }
+    if position.slice == SYNC_POINTS {
+      position.slice = 0;
+      position.pass += 1;
+    }
+    position.index = first_index(position.pass, position.slice);
+  }
+
+  /// Computes the blocks at `indices` of the segment of `lane` in `slice` of
+  /// `pass`. Earlier blocks of the segment and all earlier slices must be done.
+  ///
+  /// # Safety
+  ///
+  /// `blocks` must be the fill's blocks, and no other thread may access the
+  /// segment meanwhile. Other lanes are only read in earlier slices.
+  unsafe fn fill_segment(
+    &self,
+    blocks: Blocks,
+    pass: usize,
+    slice: usize,
+    lane: usize,
+    indices: Range<usize>,
+  ) {
+    let lane_length = self.lane_length();
+    let data_independent = match self.algorithm {
+      argon2::Algorithm::Argon2i => true,
+      argon2::Algorithm::Argon2id => pass == 0 && slice < SYNC_POINTS / 2,
+      argon2::Algorithm::Argon2d => false,
+    };
+
+    // Data-independent addressing draws from address blocks, the nth of which
+    // is G(0, G(0, input)) with counter n in the input block.
+    let mut address_block = Block::ZERO;
+    let mut input_block = Block::ZERO;
+    if data_independent {
+      input_block.0[..7].copy_from_slice(&[
+        pass as u64,
+        lane as u64,
+        slice as u64,
+        (lane_length * self.lanes) as u64,
+        self.passes as u64,
+        self.algorithm as u64,
+        (indices.start / ADDRESSES_IN_BLOCK) as u64,
+      ]);
+      if indices.start % ADDRESSES_IN_BLOCK != 0 {
+        next_addresses(&mut address_block, &mut input_block);
+      }
+    }
+
+    // Blocks are copied here in the `scratch` build. Each result is kept as
+    // the next block's previous one, so only the first is read.
+    let mut buffers = [Block::ZERO; 3];
+    let first = indices.start;
+    for index in indices {
+      let [previous_buffer, reference_buffer, current_buffer] = &mut buffers;
+      let current = lane * lane_length + slice * self.segment_length + index;
+      let previous = if index != first {
+        &*previous_buffer
+      } else if slice == 0 && index == 0 {
+        blocks.get(current + lane_length - 1, previous_buffer)
+      } else {
+        blocks.get(current - 1, previous_buffer)
+      };
+
+      let random = if data_independent {
+        if index % ADDRESSES_IN_BLOCK == 0 {
+          next_addresses(&mut address_block, &mut input_block);
+        }
+        address_block.0[index % ADDRESSES_IN_BLOCK]
+      } else {
+        previous.0[0]
+      };
+
+      let reference = self.reference_index(pass, slice, lane, index, random);
+      let reference = blocks.get(reference, reference_buffer);
+      let mut result = Block::compress(previous, reference);
+
+      if self.version != argon2::Version::V0x10 && pass != 0 {
+        result.xor(blocks.get(current, current_buffer));
+      }
+      blocks.set(current, &result);
+      *previous_buffer = result;
+    }
+    buffers.zeroize();
+  }
+
+  /// Maps the pseudo-random value of a block to the index of the block it
+  /// references.
+  fn reference_index(
+    &self,
+    pass: usize,
+    slice: usize,
+    lane: usize,
+    index: usize,
+    random: u64,
+  ) -> usize {
+    let lane_length = self.lane_length();
+    let segment_length = self.segment_length;
+
+    // Until the first slice is done, other lanes can't be referenced
+    let reference_lane = if pass == 0 && slice == 0 {
+      lane
+    } else {
+      (random >> 32) as usize % self.lanes
+    };
+
+    // Blocks that may be referenced: every finished one, excluding the
+    // previous block and, in other lanes, the current slice.
+    let finished = if pass == 0 {
+      slice * segment_length
+    } else {
+      lane_length - segment_length
+    };
+    let area_size = if reference_lane == lane {
+      finished + index - 1
+    } else if index == 0 {
+      finished - 1
+    } else {
+      finished
+    };
+
+    let x = (random & 0xFFFFFFFF).pow(2) >> 32;
+    let y = (area_size as u64 * x) >> 32;
+    let relative_position = area_size - 1 - y as usize;
+
+    let start = if pass != 0 && slice != SYNC_POINTS - 1 {
+      (slice + 1) * segment_length
+    } else {
+      0
+    };
+
+    reference_lane * lane_length + (start + relative_position) % lane_length
+  }
+
+  /// Derives the tag from the last block of every lane.
+  pub fn finish(
+    &self,
+    matrix: &mut Matrix,
+    output: &mut [u8],
+  ) -> argon2::Result<()> {
+    if output.len() != self.output_len {
+      return Err(argon2::Error::OutputTooShort);
+    }
+
+    let blocks = self.blocks(matrix);
+    let lane_length = self.lane_length();
+    let mut buffer = Block::ZERO;
+    // SAFETY: the matrix holds every block and is borrowed exclusively
+    let mut last = unsafe { *blocks.get(lane_length - 1, &mut buffer) };
+    for lane in 1..self.lanes {
+      let index = lane * lane_length + lane_length - 1;
+      last.xor(unsafe { blocks.get(index, &mut buffer) });
+    }
+    buffer.zeroize();
+
+    let mut bytes = last.to_bytes();
+    let result = blake2b_long(&[&bytes], output);
+    last.zeroize();
+    bytes.zeroize();
+    result
+  }
+}
+
+/// The segments of one slice of a [`Fill`], one per lane. They only read
+/// blocks of their own lane and of earlier slices, so different lanes may be
+/// computed concurrently.
+pub struct Slice<'a> {
+  fill: &'a Fill,
+  blocks: Blocks,
+  pass: usize,
+  slice: usize,
+}
+
+// Each lane is written by the one thread computing it; see `fill_lane`.
+unsafe impl Sync for Slice<'_> {}
+
+impl Slice<'_> {
+  pub fn lanes(&self) -> usize {
+    self.fill.lanes
+  }
+
+  /// Computes the segment of `lane`.
+  ///
+  /// # Safety
+  ///
+  /// Every lane must be computed exactly once, by a single thread, before
+  /// `fill_lanes` in [`Fill::step_slice`] returns.
+  pub unsafe fn fill_lane(&self, lane: usize) {
+    let Slice {
+      fill, pass, slice, ..
+    } = *self;
+
+    let indices = first_index(pass, slice)..fill.segment_length;
+    fill.fill_segment(self.blocks, pass, slice, lane, indices);
+  }
+}
+
+/// Index of the first block computed in a segment. The first two blocks of
+/// each lane are derived from the inputs instead.
+fn first_index(pass: usize, slice: usize) -> usize {
+  if pass == 0 && slice == 0 {
+    2
+  } else {
+    0
+  }
+}
+
+fn next_addresses(address_block: &mut Block, input_block: &mut Block) {
+  input_block.0[6] += 1;
+  *address_block = Block::compress(&Block::ZERO, input_block);
+  *address_block = Block::compress(&Block::ZERO, address_block);
+}
+
+/// The variable-length hash function H'.
+fn blake2b_long(inputs: &[&[u8]], output: &mut [u8]) -> argon2::Result<()> {
+  let len = u32::try_from(output.len())
+    .map_err(|_| argon2::Error::OutputTooLong)?
+    .to_le_bytes();
+
+  if output.len() <= 64 {
+    let mut digest = Blake2bVar::new(output.len())
+      .map_err(|_| argon2::Error::OutputTooShort)?;
+    digest::Update::update(&mut digest, &len);
+    for input in inputs {
+      digest::Update::update(&mut digest, input);
+    }
+    return digest
+      .finalize_variable(output)
+      .map_err(|_| argon2::Error::OutputTooShort);
+  }
+
+  // Longer outputs chain 64-byte hashes, keeping the first half of each
+  let mut digest = Blake2b512::new();
+  digest.update(len);
+  for input in inputs {
+    digest.update(input);
+  }
+  let mut hash = digest.finalize();
+  output[..32].copy_from_slice(&hash[..32]);
+
+  let mut written = 32;
+  while output.len() - written > 64 {
+    hash = Blake2b512::digest(hash);
+    output[written..][..32].copy_from_slice(&hash[..32]);
+    written += 32;
+  }
+
+  let mut digest = Blake2bVar::new(output.len() - written)
+    .map_err(|_| argon2::Error::OutputTooShort)?;
+  digest::Update::update(&mut digest, &hash);
+  hash.zeroize();
+  digest
+    .finalize_variable(&mut output[written..])
+    .map_err(|_| argon2::Error::OutputTooShort)
+}
+
+/// The compression function with wasm SIMD, for the `SIMD` build. Each vector
+/// holds two adjacent words, so a permutation works on eight vectors.
+#[cfg(target_feature = "simd128")]
+mod simd {
+  use super::Block;
+  use core::arch::wasm32::*;
+
+  pub fn compress(x: &Block, y: &Block) -> Block {
+    let mut r = [u64x2(0, 0); 64];
+    for (i, r) in r.iter_mut().enumerate() {
+      let [x0, x1, y0, y1] =
+        [x.0[2 * i], x.0[2 * i + 1], y.0[2 * i], y.0[2 * i + 1]];
+      *r = v128_xor(u64x2(x0, x1), u64x2(y0, y1));
+    }
+
+    let mut q = r;
+    for row in 0..8 {
+      permute(&mut q, |i| row * 8 + i);
+    }
+    for column in 0..8 {
+      permute(&mut q, |i| i * 8 + column);
+    }
+
+    let mut block = Block::ZERO;
+    for (i, (q, r)) in q.iter().zip(r).enumerate() {
+      let q = v128_xor(*q, r);
+      block.0[2 * i] = u64x2_extract_lane::<0>(q);
+      block.0[2 * i + 1] = u64x2_extract_lane::<1>(q);
+    }
+    block
+  }
+
+  /// The BlaMka permutation P over the 16 words in `vectors[index(0..8)]`.
+  #[inline(always)]
+  fn permute(vectors: &mut [v128; 64], index: impl Fn(usize) -> usize) {
+    let [mut a0, mut a1, mut b0, mut b1, mut c0, mut c1, mut d0, mut d1] =
+      core::array::from_fn(|i| vectors[index(i)]);
+
+    round(&mut a0, &mut b0, &mut c0, &mut d0);
+    round(&mut a1, &mut b1, &mut c1, &mut d1);
+
+    // Line the diagonals up in the same lanes, then put them back
+    let (mut e0, mut e1) = (shift(b0, b1), shift(b1, b0));
+    let (mut f0, mut f1) = (shift(d1, d0), shift(d0, d1));
+    round(&mut a0, &mut e0, &mut c1, &mut f0);
+    round(&mut a1, &mut e1, &mut c0, &mut f1);
+    (b0, b1) = (shift(e1, e0), shift(e0, e1));
+    (d0, d1) = (shift(f0, f1), shift(f1, f0));
+
+    for (i, vector) in [a0, a1, b0, b1, c0, c1, d0, d1].into_iter().enumerate()
+    {
+      vectors[index(i)] = vector;
+    }
+  }
+
+  /// The high word of `x` followed by the low word of `y`.
+  #[inline(always)]
+  fn shift(x: v128, y: v128) -> v128 {
+    i64x2_shuffle::<1, 2>(x, y)
+  }
+
+  /// The quarter-round GB on two columns at once.
+  #[inline(always)]
+  fn round(a: &mut v128, b: &mut v128, c: &mut v128, d: &mut v128) {
+    *a = blamka(*a, *b);
+    *d = rotate_32(v128_xor(*d, *a));
+    *c = blamka(*c, *d);
+    *b = rotate_24(v128_xor(*b, *c));
+    *a = blamka(*a, *b);
+    *d = rotate_16(v128_xor(*d, *a));
+    *c = blamka(*c, *d);
+    *b = rotate_63(v128_xor(*b, *c));
+  }
+
+  /// `x + y + 2 * lo(x) * lo(y)`, where `lo` keeps the low 32 bits.
+  #[inline(always)]
+  fn blamka(x: v128, y: v128) -> v128 {
+    let low = |v| i32x4_shuffle::<0, 2, 0, 2>(v, v);
+    let product = u64x2_extmul_low_u32x4(low(x), low(y));
+    i64x2_add(i64x2_add(x, y), i64x2_add(product, product))
+  }
+
+  #[inline(always)]
+  fn rotate_32(x: v128) -> v128 {
+    i32x4_shuffle::<1, 0, 3, 2>(x, x)
+  }
+
+  #[inline(always)]
+  fn rotate_24(x: v128) -> v128 {
+    i8x16_shuffle::<3, 4, 5, 6, 7, 0, 1, 2, 11, 12, 13, 14, 15, 8, 9, 10>(x, x)
+  }
+
+  #[inline(always)]
+  fn rotate_16(x: v128) -> v128 {
+    i8x16_shuffle::<2, 3, 4, 5, 6, 7, 0, 1, 10, 11, 12, 13, 14, 15, 8, 9>(x, x)
+  }
+
+  #[inline(always)]
+  fn rotate_63(x: v128) -> v128 {
+    v128_or(i64x2_shl(x, 1), u64x2_shr(x, 63))
+  }
+}
@@ -0,0 +1,127 @@
+use alloc::string::{String, ToString};
+use core::fmt;
+
+/// Status code for a successful call.
+pub const OK: u32 = 0;
+
+/// Errors reported by the fallible exports.
+///
+/// Exports return `0` on success and one of these discriminants otherwise.
+/// The numbering is part of the ABI, so new variants must only be appended.
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+  /// The algorithm is not one of Argon2d, Argon2i or Argon2id.
+  InvalidAlgorithm = 1,
+  /// The version is not 0x10 or 0x13.
+  InvalidVersion = 2,
+  /// The memory, time or parallelism cost is out of range.
+  InvalidParams = 3,
+  /// The salt is shorter than Argon2 allows.
+  SaltTooShort = 4,
+  /// The digest is not a well-formed Argon2 PHC string.
+  MalformedDigest = 5,
+  /// An allocation inside the module failed.
+  OutOfMemory = 6,
+  /// The password, secret or salt is too long.
+  InvalidInput = 7,
+  /// The digest exceeds the limits of the verification policy.
+  PolicyViolation = 8,
+  /// The handle does not refer to a live context.
+  InvalidHandle = 9,
+  /// The host's `should_cancel` callback asked to stop hashing.
+  Cancelled = 10,
+  /// The hash job has memory blocks left to compute.
+  Unfinished = 11,
+}
+
+impl Error {
+  /// Attaches the message that `last_error` reports for this failure.
+  pub fn with(self, message: impl fmt::Display) -> Failure {
+    Failure {
+      error: self,
+      message: message.to_string(),
+    }
+  }
+}
+
+/// An [`Error`] code together with its human-readable message.
+#[derive(Debug)]
+pub struct Failure {
+  pub error: Error,
+  pub message: String,
+}
+
+impl From<Error> for Failure {
+  fn from(error: Error) -> Self {
+    error.with(format_args!("{error:?}"))
+  }
+}
+
+pub type Result<T> = core::result::Result<T, Failure>;
+
+/// Maps a foreign error into a [`Failure`], prefixing its message.
+pub trait Context<T> {
+  fn context(self, message: &str) -> Result<T>;
+}
+
+impl<T, E: fmt::Display> Context<T> for core::result::Result<T, E>
+where
+  Error: From<E>,
+{
+  fn context(self, message: &str) -> Result<T> {
+    self.map_err(|error| {
+      let message = alloc::format!("{message}: {error}");
+      Error::from(error).with(message)
+    })
+  }
+}
+
+/// Message of the most recent failure, exposed through `last_error`.
+#[cfg_attr(feature = "atomics", thread_local)]
+static mut LAST_ERROR: String = String::new();
+
+/// Converts the result of an export into its ABI status code, remembering the
+/// message of a failure for `last_error`.
+pub fn status(result: Result<()>) -> u32 {
+  match result {
+    Ok(()) => OK,
+    Err(Failure { error, message }) => {
+      unsafe { *core::ptr::addr_of_mut!(LAST_ERROR) = message };
+      error as u32
+    }
+  }
+}
+
+/// Returns the message of the most recent failure, or an empty string.
+pub fn last_error() -> &'static str {
+  unsafe { &*core::ptr::addr_of!(LAST_ERROR) }
+}
+
+/// Clears the message of the most recent failure, returning it.
+pub fn take_last_error() -> String {
+  unsafe { core::mem::take(&mut *core::ptr::addr_of_mut!(LAST_ERROR)) }
+}
+
+impl From<argon2::Error> for Error {
+  fn from(error: argon2::Error) -> Self {
+    use argon2::Error as E;
+
+    match error {
+      E::AlgorithmInvalid => Error::InvalidAlgorithm,
+      E::VersionInvalid => Error::InvalidVersion,
+      E::SaltTooShort => Error::SaltTooShort,
+      E::B64Encoding(_) => Error::MalformedDigest,
+      E::PwdTooLong | E::SaltTooLong | E::SecretTooLong => Error::InvalidInput,
+      E::AdTooLong
+      | E::KeyIdTooLong
+      | E::MemoryTooLittle
+      | E::MemoryTooMuch
+      | E::OutputTooShort
+      | E::OutputTooLong
+      | E::ThreadsTooFew
+      | E::ThreadsTooMany
+      | E::TimeTooSmall => Error::InvalidParams,
+    }
+  }
+}
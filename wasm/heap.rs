@@ -0,0 +1,147 @@
+//! dlmalloc heap whose linear memory can be handed back to it after a trap.
+//!
+//! Wasm memory can't shrink, so a fresh allocator would have to grow memory
+//! again. Instead [`Pages`] remembers the pages it has given dlmalloc and
+//! [`Heap::reset`] starts a new dlmalloc instance on top of them.
+//!
+//! The heap is locked around every call, so that threads sharing memory in
+//! the `atomics` build can allocate concurrently.
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::UnsafeCell;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering::*};
+use dlmalloc::Dlmalloc;
+
+const PAGE_SIZE: usize = 64 * 1024;
+
+/// Start of the run of pages owned by the heap.
+static BASE: AtomicUsize = AtomicUsize::new(0);
+/// First byte of the run not yet handed to dlmalloc.
+static NEXT: AtomicUsize = AtomicUsize::new(0);
+/// End of the run of pages owned by the heap.
+static END: AtomicUsize = AtomicUsize::new(0);
+
+/// Supplies dlmalloc with memory, reusing pages released by a reset before
+/// growing linear memory. Only called with the heap locked.
+struct Pages;
+
+unsafe impl dlmalloc::Allocator for Pages {
+  fn alloc(&self, size: usize) -> (*mut u8, usize, u32) {
+    let mut next = NEXT.load(Relaxed);
+    let mut end = END.load(Relaxed);
+
+    while end - next < size {
+      let pages = (size - (end - next)).div_ceil(PAGE_SIZE);
+      let previous = crate::arch::memory_grow(0, pages);
+      if previous == usize::MAX {
+        return (ptr::null_mut(), 0, 0);
+      }
+
+      // Pages normally extend the run, unless the host grew memory, e.g.
+      // for a thread's stack. Then start a new run; the old one is never
+      // reused.
+      let start = previous * PAGE_SIZE;
+      if start != end {
+        BASE.store(start, Relaxed);
+        next = start;
+      }
+      end = start + pages * PAGE_SIZE;
+    }
+
+    NEXT.store(next + size, Relaxed);
+    END.store(end, Relaxed);
+    (next as *mut u8, size, 0)
+  }
+
+  fn remap(&self, _: *mut u8, _: usize, _: usize, _: bool) -> *mut u8 {
+    ptr::null_mut()
+  }
+
+  fn free_part(&self, _: *mut u8, _: usize, _: usize) -> bool {
+    false
+  }
+
+  fn free(&self, _: *mut u8, _: usize) -> bool {
+    false
+  }
+
+  fn can_release_part(&self, _: u32) -> bool {
+    false
+  }
+
+  fn allocates_zeros(&self) -> bool {
+    // Pages reused after a reset still hold old data.
+    false
+  }
+
+  fn page_size(&self) -> usize {
+    PAGE_SIZE
+  }
+}
+
+pub struct Heap {
+  dlmalloc: UnsafeCell<Dlmalloc<Pages>>,
+  locked: AtomicBool,
+}
+
+// Access to dlmalloc is serialized by `locked`.
+unsafe impl Sync for Heap {}
+
+impl Heap {
+  pub const fn new() -> Self {
+    Heap {
+      dlmalloc: UnsafeCell::new(Dlmalloc::new_with_allocator(Pages)),
+      locked: AtomicBool::new(false),
+    }
+  }
+
+  /// Runs `f` with exclusive access to dlmalloc. The lock spins rather than
+  /// waits, since allocator calls are short and browsers don't allow waiting
+  /// on their main thread.
+  fn with<T>(&self, f: impl FnOnce(&mut Dlmalloc<Pages>) -> T) -> T {
+    while self.locked.swap(true, Acquire) {
+      core::hint::spin_loop();
+    }
+    let result = f(unsafe { &mut *self.dlmalloc.get() });
+    self.locked.store(false, Release);
+    result
+  }
+
+  /// Forgets every allocation, making all of the heap's pages available
+  /// again. Pointers into the heap must not be used afterwards.
+  ///
+  /// The lock is released rather than taken, since a trap inside [`Heap::with`]
+  /// leaves it held. Without `atomics` nothing else can be holding it.
+  #[cfg(not(feature = "atomics"))]
+  pub unsafe fn reset(&self) {
+    NEXT.store(BASE.load(Relaxed), Relaxed);
+    ptr::write(self.dlmalloc.get(), Dlmalloc::new_with_allocator(Pages));
+    self.locked.store(false, Release);
+  }
+}
+
+unsafe impl GlobalAlloc for Heap {
+  unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+    self.with(|dlmalloc| dlmalloc.malloc(layout.size(), layout.align()))
+  }
+
+  unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    self.with(|dlmalloc| dlmalloc.free(ptr, layout.size(), layout.align()))
+  }
+
+  unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+    self.with(|dlmalloc| dlmalloc.calloc(layout.size(), layout.align()))
+  }
+
+  unsafe fn realloc(
+    &self,
+    ptr: *mut u8,
+    layout: Layout,
+    new_size: usize,
+  ) -> *mut u8 {
+    self.with(|dlmalloc| {
+      dlmalloc.realloc(ptr, layout.size(), layout.align(), new_size)
+    })
+  }
+}
@@ -0,0 +1,40 @@
+use crate::engine::Fill;
+use crate::error::Result;
+use crate::matrix::Matrix;
+use crate::slab::Slab;
+use crate::AllParams;
+use alloc::vec::Vec;
+
+/// A hash computed over several calls: the Argon2 fill in progress, its
+/// memory blocks, and what is needed to encode the finished digest. The
+/// blocks are wiped when the job is dropped.
+pub struct HashJob {
+  pub params: AllParams,
+  pub salt: Vec<u8>,
+  pub fill: Fill,
+  pub blocks: Matrix,
+}
+
+#[cfg_attr(feature = "atomics", thread_local)]
+static mut JOBS: Slab<HashJob> = Slab::new("hash job");
+
+fn jobs() -> &'static mut Slab<HashJob> {
+  unsafe { &mut *core::ptr::addr_of_mut!(JOBS) }
+}
+
+pub fn insert(job: HashJob) -> u32 {
+  jobs().insert(job)
+}
+
+/// Removes every job, returning them to the caller to drop or forget.
+pub fn take_all() -> Vec<Option<HashJob>> {
+  jobs().take_all()
+}
+
+pub fn get(handle: u32) -> Result<&'static mut HashJob> {
+  jobs().get(handle)
+}
+
+pub fn remove(handle: u32) -> Result<HashJob> {
+  jobs().remove(handle)
+}
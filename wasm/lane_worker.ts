@@ -0,0 +1,26 @@
+/// <reference lib="deno.worker" />
+// Hosts a lane worker of the threaded build: an instance of the module that
+// shares the hashing instance's memory and computes Argon2 lanes for it. It is
+// started by `wasm/mod.ts` with the compiled module and the shared memory.
+import { setupThread } from "./thread.ts";
+
+self.onmessage = async (event: MessageEvent) => {
+  const { module, memory } = event.data;
+
+  const instance = await WebAssembly.instantiate(module, {
+    env: {
+      memory,
+      should_cancel: () => 0,
+      progress: () => {},
+      panic: (ptr: number, len: number) => {
+        const msg = new TextDecoder().decode(
+          new Uint8Array(memory.buffer, ptr, len).slice(),
+        );
+        throw new Error(msg);
+      },
+    },
+  });
+
+  setupThread(instance.exports, memory);
+  (instance.exports.lane_worker as () => void)();
+};
@@ -1,35 +1,126 @@
 #![no_std]
 #![feature(alloc_error_handler, const_mut_refs, allocator_api)]
+#![cfg_attr(feature = "atomics", feature(thread_local))]
+#![cfg_attr(target_arch = "wasm64", feature(simd_wasm64))]
+
+//! In the `atomics` build several threads may call into one shared memory,
+//! each through an instance of its own. State that calls leave behind, such as
+//! the error message, parameters, policy, contexts and hash jobs, is then kept
+//! per thread in thread-local statics, and the heap is shared behind a lock.
+//! Each instance needs a stack and thread-local storage of its own before its
+//! first call; see `wasm/thread.ts`.
+//!
+//! The `memory64` build targets `wasm64-unknown-unknown`. Pointers and sizes
+//! in the exports are `usize`, so they become 64-bit there, and the block
+//! matrix may exceed 4 GiB.
 
 extern crate alloc;
 
-use argon2::{password_hash::Salt, Argon2, PasswordHash, PasswordVerifier};
-use base64::Engine;
-
-#[global_allocator]
-static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
+mod allocator;
+mod capabilities;
+mod context;
+mod engine;
+mod error;
+#[cfg(feature = "dlmalloc")]
+mod heap;
+mod job;
+mod matrix;
+mod phc;
+mod policy;
+mod slab;
+#[cfg(feature = "threads")]
+mod threads;
+
+/// Memory instructions of the target, whose addresses are 64-bit in the
+/// `memory64` build.
+#[cfg(target_arch = "wasm32")]
+use core::arch::wasm32 as arch;
+#[cfg(target_arch = "wasm64")]
+use core::arch::wasm64 as arch;
+
+use alloc::{vec, vec::Vec};
+use context::HasherContext;
+use engine::{Fill, Position};
+use error::{status, Context, Error, Result};
+use job::HashJob;
+use matrix::Matrix;
+use phc::Phc;
+use policy::VerifyPolicy;
+#[cfg(feature = "threads")]
+use threads::fill_lanes;
+use zeroize::{Zeroize, Zeroizing};
 
 extern "C" {
   fn panic(ptr: *const u8, len: usize);
+
+  /// Polled after each slice of the Argon2 computation; a non-zero result
+  /// abandons it with [`Error::Cancelled`].
+  fn should_cancel() -> u32;
+
+  /// Called after each segment of the Argon2 computation with the segment's
+  /// position and the fraction of the computation done.
+  fn progress(pass: u32, slice: u32, lane: u32, fraction: f64);
 }
 
+/// Set when a call panics, since the trap that follows can leave module state
+/// half updated. Cleared by [`reset`].
+#[cfg_attr(feature = "atomics", thread_local)]
+static mut POISONED: bool = false;
+
+/// Panic messages are formatted here rather than on the heap, which may be
+/// exhausted or inconsistent by the time something panics.
+#[cfg_attr(feature = "atomics", thread_local)]
+static mut PANIC_MESSAGE: [u8; 1024] = [0; 1024];
+
 #[panic_handler]
 #[no_mangle]
 pub fn panic_handler(info: &core::panic::PanicInfo) -> ! {
-  let msg = alloc::format!("{info}");
-  let ptr = msg.as_ptr();
-  let len = msg.capacity();
-  unsafe { panic(ptr, len) };
+  unsafe { POISONED = true };
+
+  let buffer = unsafe { &mut *core::ptr::addr_of_mut!(PANIC_MESSAGE) };
+  let mut msg = Truncate { buffer, len: 0 };
+  let _ = core::fmt::Write::write_fmt(&mut msg, format_args!("{info}"));
+  unsafe { panic(msg.buffer.as_ptr(), msg.len) };
 
   loop {}
 }
 
+/// Writes into a fixed buffer, dropping whatever doesn't fit.
+struct Truncate<'a> {
+  buffer: &'a mut [u8],
+  len: usize,
+}
+
+impl core::fmt::Write for Truncate<'_> {
+  fn write_str(&mut self, s: &str) -> core::fmt::Result {
+    let len = s.len().min(self.buffer.len() - self.len);
+    self.buffer[self.len..][..len].copy_from_slice(&s.as_bytes()[..len]);
+    self.len += len;
+    Ok(())
+  }
+}
+
 #[alloc_error_handler]
 #[no_mangle]
 pub fn alloc_error_handler(layout: core::alloc::Layout) -> ! {
   panic!("Memory allocation of {} bytes failed", layout.size());
 }
 
+/// Returns the [`capabilities::ABI_VERSION`] the exports follow. Hosts should
+/// check it before calling anything else, which may have another signature.
+#[no_mangle]
+pub fn abi_version() -> u32 {
+  capabilities::ABI_VERSION
+}
+
+/// Returns a pointer to the [`capabilities::Capabilities`] of this build:
+/// supported algorithms and versions, feature bits and parameter limits, each
+/// a `u32`. They are in static memory and never change.
+#[no_mangle]
+pub fn capabilities() -> *const capabilities::Capabilities {
+  &capabilities::CAPABILITIES
+}
+
 #[no_mangle]
 pub unsafe fn alloc(size: usize) -> *mut u8 {
   let align = core::mem::align_of::<usize>();
@@ -44,6 +135,86 @@ pub unsafe fn dealloc(ptr: *mut u8, size: usize) {
   alloc::alloc::dealloc(ptr, layout);
 }
 
+/// Like [`dealloc`], first overwriting the buffer with zeros. Hosts should free
+/// buffers that held passwords, secrets or derived keys with this.
+#[no_mangle]
+pub unsafe fn dealloc_zeroize(ptr: *mut u8, size: usize) {
+  core::slice::from_raw_parts_mut(ptr, size).zeroize();
+  dealloc(ptr, size);
+}
+
+/// Returns `1` if a call has panicked since the module was instantiated or
+/// last [`reset`], and `0` otherwise.
+#[no_mangle]
+pub fn is_poisoned() -> u32 {
+  unsafe { POISONED as u32 }
+}
+
+/// Restores the module after a trap without reinstantiating it. The parameters
+/// of [`setup_params`] go back to their defaults, every context and hash job is
+/// freed and, with the `dlmalloc` allocator, the whole heap is reclaimed,
+/// including allocations leaked by the call that trapped. Every pointer and
+/// handle held by the host is invalid afterwards. The verification policy and
+/// memory limit are kept.
+///
+/// In the `atomics` build only the calling thread's state is reset. The heap
+/// is shared with other threads and kept, so allocations leaked by the trapped
+/// call stay allocated.
+#[no_mangle]
+pub unsafe fn reset() {
+  PARAMS = AllParams::DEFAULT;
+
+  let state = (
+    context::take_all(),
+    job::take_all(),
+    error::take_last_error(),
+  );
+  if allocator::reset_heap() {
+    // The heap was reclaimed as a whole, so nothing in it may be freed again.
+    core::mem::forget(state);
+  }
+
+  POISONED = false;
+}
+
+/// Snapshot returned by [`memory_stats`], kept in static memory so reading
+/// the statistics doesn't itself allocate.
+#[cfg_attr(feature = "atomics", thread_local)]
+static mut MEMORY_STATS: allocator::MemoryStats = allocator::MemoryStats {
+  allocated: 0,
+  peak: 0,
+  allocations: 0,
+  pages: 0,
+};
+
+/// Returns a pointer to the current [`allocator::MemoryStats`]: bytes
+/// allocated, their high-water mark, live allocations and wasm pages, each a
+/// `usize`. The snapshot is overwritten by the next call.
+#[no_mangle]
+pub unsafe fn memory_stats() -> *const allocator::MemoryStats {
+  let stats = core::ptr::addr_of_mut!(MEMORY_STATS);
+  *stats = allocator::memory_stats();
+  stats
+}
+
+/// Limits the bytes the module may have allocated at once. Argon2 memory is
+/// checked against the limit before hashing starts, so exceeding it fails the
+/// call with an out of memory status instead of trapping. Pass `usize::MAX` to
+/// remove the limit.
+#[no_mangle]
+pub fn set_memory_limit(max_bytes: usize) {
+  allocator::set_memory_limit(max_bytes);
+}
+
+/// Writes the pointer and length of the message describing the most recent
+/// failed call. The message is owned by the module and must not be freed.
+#[no_mangle]
+pub unsafe fn last_error(output_ptr: *mut *const u8, output_len: *mut usize) {
+  let message = error::last_error();
+  *output_ptr = message.as_ptr();
+  *output_len = message.len();
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 struct AllParams {
   algorithm: argon2::Algorithm,
@@ -51,15 +222,90 @@ struct AllParams {
   m_cost: u32,
   t_cost: u32,
   p_cost: u32,
+  output_len: usize,
+  data: argon2::AssociatedData,
 }
 
-static mut PARAMS: AllParams = AllParams {
-  algorithm: argon2::Algorithm::Argon2id,
-  version: argon2::Version::V0x13,
-  m_cost: argon2::Params::DEFAULT_M_COST,
-  t_cost: argon2::Params::DEFAULT_T_COST,
-  p_cost: argon2::Params::DEFAULT_P_COST,
-};
+impl AllParams {
+  const DEFAULT: AllParams = AllParams {
+    algorithm: argon2::Algorithm::Argon2id,
+    version: argon2::Version::V0x13,
+    m_cost: argon2::Params::DEFAULT_M_COST,
+    t_cost: argon2::Params::DEFAULT_T_COST,
+    p_cost: argon2::Params::DEFAULT_P_COST,
+    output_len: argon2::Params::DEFAULT_OUTPUT_LEN,
+    data: argon2::AssociatedData::EMPTY,
+  };
+
+  fn new(
+    algorithm: [u8; 4],
+    version: u32,
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+    output_len: usize,
+  ) -> Result<Self> {
+    let algorithm = match &algorithm {
+      b"i___" => argon2::Algorithm::Argon2i,
+      b"d___" => argon2::Algorithm::Argon2d,
+      b"id__" => argon2::Algorithm::Argon2id,
+      _ => return Err(Error::InvalidAlgorithm.with("Invalid algorithm")),
+    };
+
+    let version = match version {
+      0x10 => argon2::Version::V0x10,
+      0x13 => argon2::Version::V0x13,
+      _ => return Err(Error::InvalidVersion.with("Invalid version")),
+    };
+
+    let params = AllParams {
+      algorithm,
+      version,
+      m_cost,
+      t_cost,
+      p_cost,
+      output_len,
+      data: argon2::AssociatedData::EMPTY,
+    };
+    params.argon2_params()?;
+
+    Ok(params)
+  }
+
+  /// Binds the hash to associated data, emitted as `data=` in PHC strings.
+  fn with_data(mut self, data: Option<&[u8]>) -> Result<Self> {
+    if let Some(data) = data {
+      self.data =
+        argon2::AssociatedData::new(data).context("Invalid associated data")?;
+    }
+    Ok(self)
+  }
+
+  fn argon2_params(&self) -> Result<argon2::Params> {
+    argon2::ParamsBuilder::new()
+      .m_cost(self.m_cost)
+      .t_cost(self.t_cost)
+      .p_cost(self.p_cost)
+      .output_len(self.output_len)
+      .data(self.data)
+      .build()
+      .context("Invalid parameter memory, time, or paralellism")
+  }
+
+  fn hasher<'a>(&self, secret: Option<&'a [u8]>) -> Result<Hasher<'a>> {
+    Ok(Hasher {
+      algorithm: self.algorithm,
+      version: self.version,
+      params: self.argon2_params()?,
+      secret,
+    })
+  }
+}
+
+/// Parameters used by [`hash`], kept only for compatibility with hosts that
+/// still call [`setup_params`]. New code should call [`hash_with_params`].
+#[cfg_attr(feature = "atomics", thread_local)]
+static mut PARAMS: AllParams = AllParams::DEFAULT;
 
 #[no_mangle]
 pub unsafe fn setup_params(
@@ -68,38 +314,39 @@ pub unsafe fn setup_params(
   m_cost: u32,
   t_cost: u32,
   p_cost: u32,
-) {
-  let algorithm = match &algorithm {
-    b"i___" => argon2::Algorithm::Argon2i,
-    b"d___" => argon2::Algorithm::Argon2d,
-    b"id__" => argon2::Algorithm::Argon2id,
-    _ => panic!("Invalid algorithm"),
-  };
+) -> u32 {
+  let output_len = argon2::Params::DEFAULT_OUTPUT_LEN;
+  let params =
+    AllParams::new(algorithm, version, m_cost, t_cost, p_cost, output_len);
 
-  let version = match version {
-    0x10 => argon2::Version::V0x10,
-    0x13 => argon2::Version::V0x13,
-    _ => panic!("Invalid version"),
-  };
+  status(params.map(|params| PARAMS = params))
+}
 
-  let params = argon2::ParamsBuilder::new()
-    .m_cost(m_cost)
-    .t_cost(t_cost)
-    .p_cost(p_cost)
-    .build()
-    .expect("Invalid parameter memory, time, or paralellism");
+#[no_mangle]
+pub unsafe fn hash(
+  password_ptr: *const u8,
+  password_len: usize,
 
-    PARAMS = AllParams {
-      algorithm,
-      version,
-      m_cost: params.m_cost(),
-      t_cost: params.t_cost(),
-      p_cost: params.p_cost(),
-    };
+  salt_ptr: *const u8,
+  salt_len: usize,
+
+  secret_ptr: *const u8,
+  secret_len: usize,
+
+  output_ptr: *mut *mut u8,
+) -> u32 {
+  let password = core::slice::from_raw_parts(password_ptr, password_len);
+  let salt = core::slice::from_raw_parts(salt_ptr, salt_len);
+  let secret = optional_slice(secret_ptr, secret_len);
+
+  let digest = try_hash(password, salt, secret, PARAMS, &mut Matrix::new());
+
+  status(digest.and_then(|digest| write_digest(digest, output_ptr)))
 }
 
 #[no_mangle]
-pub unsafe fn hash(
+#[allow(clippy::too_many_arguments)]
+pub unsafe fn hash_with_params(
   password_ptr: *const u8,
   password_len: usize,
 
@@ -109,40 +356,229 @@ pub unsafe fn hash(
   secret_ptr: *const u8,
   secret_len: usize,
 
+  data_ptr: *const u8,
+  data_len: usize,
+
+  algorithm: [u8; 4],
+  version: u32,
+  m_cost: u32,
+  t_cost: u32,
+  p_cost: u32,
+  output_len: usize,
+
   output_ptr: *mut *mut u8,
-) {
+) -> u32 {
   let password = core::slice::from_raw_parts(password_ptr, password_len);
-  let secret = if !secret_ptr.is_null() {
-    Some(core::slice::from_raw_parts(secret_ptr, secret_len))
+  let salt = core::slice::from_raw_parts(salt_ptr, salt_len);
+  let secret = optional_slice(secret_ptr, secret_len);
+  let data = optional_slice(data_ptr, data_len);
+
+  let params =
+    AllParams::new(algorithm, version, m_cost, t_cost, p_cost, output_len)
+      .and_then(|params| params.with_data(data));
+
+  let digest = params.and_then(|params| {
+    try_hash(password, salt, secret, params, &mut Matrix::new())
+  });
+
+  status(digest.and_then(|digest| write_digest(digest, output_ptr)))
+}
+
+unsafe fn optional_slice<'a>(ptr: *const u8, len: usize) -> Option<&'a [u8]> {
+  if !ptr.is_null() {
+    Some(core::slice::from_raw_parts(ptr, len))
   } else {
     None
-  };
+  }
+}
 
-  let salt = core::slice::from_raw_parts(salt_ptr, salt_len);
-  let salt = base64::engine::general_purpose::STANDARD_NO_PAD.encode(salt);
-  let salt = Salt::from_b64(&salt).expect("Got invalid salt");
+/// An Argon2 variant and parameters, keyed by an optional secret.
+struct Hasher<'a> {
+  algorithm: argon2::Algorithm,
+  version: argon2::Version,
+  params: argon2::Params,
+  secret: Option<&'a [u8]>,
+}
 
-  let AllParams { algorithm, version, m_cost, t_cost, p_cost } = PARAMS;
-  let params = argon2::Params::new(m_cost, t_cost, p_cost, None).unwrap();
+impl Hasher<'_> {
+  /// Checks the inputs of a hash, before its blocks are allocated.
+  fn check(&self, password: &[u8], salt: &[u8]) -> Result<()> {
+    engine::check_inputs(password, salt, self.secret)
+      .context("Failed to hash password")
+  }
 
-  let hasher = if let Some(secret) = secret {
-    Argon2::new_with_secret(secret, algorithm, version, params).unwrap()
-  } else {
-    Argon2::new(algorithm, version, params)
-  };
+  /// Starts filling `blocks`, which must hold the parameters' block count.
+  fn start(
+    &self,
+    password: &[u8],
+    salt: &[u8],
+    blocks: &mut Matrix,
+  ) -> Result<Fill> {
+    let Hasher {
+      algorithm,
+      version,
+      ref params,
+      secret,
+    } = *self;
+
+    Fill::start(algorithm, version, params, password, salt, secret, blocks)
+      .context("Failed to hash password")
+  }
+}
+
+/// Runs Argon2 using `blocks` as its working memory, growing them to the
+/// number of blocks the hasher's parameters need. Contexts keep their blocks
+/// between calls; one-shot calls pass an empty matrix. The blocks are wiped
+/// afterwards, since they are derived from the password.
+fn hash_into(
+  hasher: &Hasher,
+  password: &[u8],
+  salt: &[u8],
+  output: &mut [u8],
+  blocks: &mut Matrix,
+) -> Result<()> {
+  hasher.check(password, salt)?;
+  blocks.grow(hasher.params.block_count())?;
+
+  let result = fill(hasher, password, salt, output, blocks);
+  blocks.wipe();
+
+  result
+}
+
+fn fill(
+  hasher: &Hasher,
+  password: &[u8],
+  salt: &[u8],
+  output: &mut [u8],
+  blocks: &mut Matrix,
+) -> Result<()> {
+  let mut fill = hasher.start(password, salt, blocks)?;
+
+  while !fill.is_done() {
+    fill.step_slice(blocks, fill_lanes, |fill, position| {
+      let Position {
+        pass, slice, lane, ..
+      } = position;
+      unsafe {
+        progress(pass as u32, slice as u32, lane as u32, fill.progress())
+      };
+    });
+
+    if unsafe { should_cancel() } != 0 {
+      return Err(Error::Cancelled.with("Hashing was cancelled"));
+    }
+  }
+
+  fill
+    .finish(blocks, output)
+    .context("Failed to hash password")
+}
+
+/// Computes the lanes of a slice one after the other.
+#[cfg(not(feature = "threads"))]
+fn fill_lanes(slice: &engine::Slice) {
+  for lane in 0..slice.lanes() {
+    unsafe { slice.fill_lane(lane) };
+  }
+}
+
+fn try_hash(
+  password: &[u8],
+  salt: &[u8],
+  secret: Option<&[u8]>,
+  params: AllParams,
+  blocks: &mut Matrix,
+) -> Result<Phc> {
+  let hasher = params.hasher(secret)?;
 
-  let hash = PasswordHash::generate(hasher, password, salt).expect("Failed to hash password");
-  let digest = alloc::string::ToString::to_string(&hash);
+  let mut hash = vec![0; params.output_len];
+  hash_into(&hasher, password, salt, &mut hash, blocks)?;
 
-  let mut digest = digest.into_bytes();
-  digest.push(0);
+  encode_digest(params, salt, hash)
+}
+
+fn encode_digest(params: AllParams, salt: &[u8], hash: Vec<u8>) -> Result<Phc> {
+  Ok(Phc {
+    algorithm: params.algorithm,
+    version: params.version,
+    params: params.argon2_params()?,
+    salt: salt.to_vec(),
+    hash,
+  })
+}
 
-  let digest_output = alloc(digest.len());
-  for i in 0..digest.len() {
-    *digest_output.add(i) = digest[i];
+/// Formats a digest into a new NUL-terminated allocation owned by the host.
+/// Its length is measured first so it is written there directly, leaving no
+/// copies in the module's memory.
+unsafe fn write_digest(digest: Phc, output_ptr: *mut *mut u8) -> Result<()> {
+  let len = digest.encoded_len();
+  let digest_output = alloc(len + 1);
+  if digest_output.is_null() {
+    return Err(Error::OutOfMemory.with("Failed to allocate hash digest"));
   }
 
+  let output = core::slice::from_raw_parts_mut(digest_output, len + 1);
+  digest.encode_into(&mut output[..len]);
+  output[len] = 0;
+
   *output_ptr = digest_output;
+
+  Ok(())
+}
+
+/// Computes the raw Argon2 tag (KDF mode), writing exactly `output_len` bytes
+/// to `output_ptr`.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe fn hash_raw(
+  password_ptr: *const u8,
+  password_len: usize,
+
+  salt_ptr: *const u8,
+  salt_len: usize,
+
+  secret_ptr: *const u8,
+  secret_len: usize,
+
+  data_ptr: *const u8,
+  data_len: usize,
+
+  algorithm: [u8; 4],
+  version: u32,
+  m_cost: u32,
+  t_cost: u32,
+  p_cost: u32,
+
+  output_ptr: *mut u8,
+  output_len: usize,
+) -> u32 {
+  let password = core::slice::from_raw_parts(password_ptr, password_len);
+  let salt = core::slice::from_raw_parts(salt_ptr, salt_len);
+  let secret = optional_slice(secret_ptr, secret_len);
+  let data = optional_slice(data_ptr, data_len);
+  let output = core::slice::from_raw_parts_mut(output_ptr, output_len);
+
+  let params =
+    AllParams::new(algorithm, version, m_cost, t_cost, p_cost, output_len)
+      .and_then(|params| params.with_data(data));
+
+  status(
+    params
+      .and_then(|params| try_hash_raw(password, salt, secret, params, output)),
+  )
+}
+
+fn try_hash_raw(
+  password: &[u8],
+  salt: &[u8],
+  secret: Option<&[u8]>,
+  params: AllParams,
+  output: &mut [u8],
+) -> Result<()> {
+  let hasher = params.hasher(secret)?;
+
+  hash_into(&hasher, password, salt, output, &mut Matrix::new())
 }
 
 #[no_mangle]
@@ -152,44 +588,386 @@ pub unsafe fn verify(
 
   password_ptr: *const u8,
   password_len: usize,
-  
+
   secret_ptr: *const u8,
   secret_len: usize,
 
   matches: *mut u32,
+) -> u32 {
+  let digest = core::slice::from_raw_parts(digest_ptr, digest_len);
+  let password = core::slice::from_raw_parts(password_ptr, password_len);
+  let secret = optional_slice(secret_ptr, secret_len);
+
+  let result = try_verify(digest, password, secret);
+
+  status(result.map(|password_valid| *matches = password_valid as u32))
+}
+
+/// Sets the limits `verify` and `verify_and_upgrade` enforce on digests before
+/// computing them. See [`VerifyPolicy`] for the bitmask layout.
+#[no_mangle]
+pub fn set_verify_policy(
+  max_m_cost: u32,
+  max_t_cost: u32,
+  max_p_cost: u32,
+  algorithms: u32,
+  versions: u32,
 ) {
+  policy::set_verify_policy(VerifyPolicy {
+    max_m_cost,
+    max_t_cost,
+    max_p_cost,
+    algorithms,
+    versions,
+  });
+}
+
+fn parse_digest(digest: &[u8]) -> Result<Phc> {
+  let digest = core::str::from_utf8(digest)
+    .map_err(|_| Error::MalformedDigest.with("Invalid hash digest"))?;
+
+  Phc::parse(digest)
+}
+
+fn try_verify(
+  digest: &[u8],
+  password: &[u8],
+  secret: Option<&[u8]>,
+) -> Result<bool> {
+  verify_phc(&parse_digest(digest)?, password, secret, &mut Matrix::new())
+}
+
+fn verify_phc(
+  phc: &Phc,
+  password: &[u8],
+  secret: Option<&[u8]>,
+  blocks: &mut Matrix,
+) -> Result<bool> {
+  policy::verify_policy().check(phc)?;
+
+  let hasher = Hasher {
+    algorithm: phc.algorithm,
+    version: phc.version,
+    params: phc.params.clone(),
+    secret,
+  };
+
+  let mut expected = Zeroizing::new(vec![0; phc.hash.len()]);
+  hash_into(&hasher, password, &phc.salt, &mut expected, blocks)?;
+
+  Ok(phc::ct_eq(&phc.hash, &expected))
+}
+
+/// Reports through `outdated` whether a digest was produced with weaker
+/// settings than the target: a different algorithm, an older version, lower
+/// costs, or a shorter tag or salt. The digest is only parsed, not recomputed.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe fn needs_rehash(
+  digest_ptr: *const u8,
+  digest_len: usize,
+
+  algorithm: [u8; 4],
+  version: u32,
+  m_cost: u32,
+  t_cost: u32,
+  p_cost: u32,
+  output_len: usize,
+  salt_len: usize,
+
+  outdated: *mut u32,
+) -> u32 {
   let digest = core::slice::from_raw_parts(digest_ptr, digest_len);
-  let digest = core::str::from_utf8(digest).expect("Invalid hash digest");
 
+  let result =
+    AllParams::new(algorithm, version, m_cost, t_cost, p_cost, output_len)
+      .and_then(|target| {
+        let phc = parse_digest(digest)?;
+        Ok(is_outdated(&phc, &target, salt_len))
+      });
+
+  status(result.map(|is_outdated| *outdated = is_outdated as u32))
+}
+
+fn is_outdated(phc: &Phc, target: &AllParams, salt_len: usize) -> bool {
+  phc.algorithm != target.algorithm
+    || u32::from(phc.version) < u32::from(target.version)
+    || phc.params.m_cost() < target.m_cost
+    || phc.params.t_cost() < target.t_cost
+    || phc.params.p_cost() < target.p_cost
+    || phc.hash.len() < target.output_len
+    || phc.salt.len() < salt_len
+}
+
+/// Verifies a password and, only if it matches and the digest is outdated
+/// compared to the target parameters (see [`needs_rehash`]), writes a new
+/// digest computed with those parameters and `salt` to `output_ptr`.
+/// Otherwise a null pointer is written there.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe fn verify_and_upgrade(
+  digest_ptr: *const u8,
+  digest_len: usize,
+
+  password_ptr: *const u8,
+  password_len: usize,
+
+  secret_ptr: *const u8,
+  secret_len: usize,
+
+  salt_ptr: *const u8,
+  salt_len: usize,
+
+  data_ptr: *const u8,
+  data_len: usize,
+
+  algorithm: [u8; 4],
+  version: u32,
+  m_cost: u32,
+  t_cost: u32,
+  p_cost: u32,
+  output_len: usize,
+
+  matches: *mut u32,
+  output_ptr: *mut *mut u8,
+) -> u32 {
+  let digest = core::slice::from_raw_parts(digest_ptr, digest_len);
   let password = core::slice::from_raw_parts(password_ptr, password_len);
-  let secret = if !secret_ptr.is_null() {
-    Some(core::slice::from_raw_parts(secret_ptr, secret_len))
-  } else {
-    None
-  };
+  let secret = optional_slice(secret_ptr, secret_len);
+  let salt = core::slice::from_raw_parts(salt_ptr, salt_len);
+  let data = optional_slice(data_ptr, data_len);
 
-  let hash = PasswordHash::new(digest).expect("Invalid digest format");
-  let params = argon2::Params::try_from(&hash).expect("Invalid digest parameters");
-  let algorithm = match hash.algorithm.as_str() {
-    "argon2i" => argon2::Algorithm::Argon2i,
-    "argon2d" => argon2::Algorithm::Argon2d,
-    "argon2id" => argon2::Algorithm::Argon2id,
-    _ => panic!("Invalid algorithm"),
-  };
-  let version = match hash.version {
-    Some(0x10) => argon2::Version::V0x10,
-    Some(0x13) => argon2::Version::V0x13,
-    None => argon2::Version::default(),
-    Some(_) => panic!("Invalid {algorithm} version"),
-  };
+  *output_ptr = core::ptr::null_mut();
 
-  let hasher = if let Some(secret) = secret {
-    Argon2::new_with_secret(secret, algorithm, version, params).unwrap()
-  } else {
-    Argon2::new(algorithm, version, params)
-  };
+  let target =
+    AllParams::new(algorithm, version, m_cost, t_cost, p_cost, output_len)
+      .and_then(|params| params.with_data(data));
+
+  status(target.and_then(|target| {
+    let phc = parse_digest(digest)?;
+    let mut blocks = Matrix::new();
+    let password_valid = verify_phc(&phc, password, secret, &mut blocks)?;
+    *matches = password_valid as u32;
+
+    if password_valid && is_outdated(&phc, &target, salt.len()) {
+      let digest = try_hash(password, salt, secret, target, &mut blocks)?;
+      write_digest(digest, output_ptr)?;
+    }
+
+    Ok(())
+  }))
+}
+
+/// Creates a hasher context for the given parameters, preallocating the
+/// Argon2 memory blocks they need, and writes its handle to `handle_ptr`.
+#[no_mangle]
+pub unsafe fn context_new(
+  algorithm: [u8; 4],
+  version: u32,
+  m_cost: u32,
+  t_cost: u32,
+  p_cost: u32,
+  output_len: usize,
 
-  let password_valid = hasher.verify_password(password, &hash).is_ok();
+  handle_ptr: *mut u32,
+) -> u32 {
+  let params =
+    AllParams::new(algorithm, version, m_cost, t_cost, p_cost, output_len);
 
-  *matches = password_valid as u32;
+  status(params.and_then(|params| {
+    let mut blocks = Matrix::new();
+    blocks.grow(params.argon2_params()?.block_count())?;
+
+    *handle_ptr = context::insert(HasherContext { params, blocks });
+    Ok(())
+  }))
+}
+
+/// Like [`hash_with_params`], using the parameters and memory of a context.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe fn context_hash(
+  handle: u32,
+
+  password_ptr: *const u8,
+  password_len: usize,
+
+  salt_ptr: *const u8,
+  salt_len: usize,
+
+  secret_ptr: *const u8,
+  secret_len: usize,
+
+  data_ptr: *const u8,
+  data_len: usize,
+
+  output_ptr: *mut *mut u8,
+) -> u32 {
+  let password = core::slice::from_raw_parts(password_ptr, password_len);
+  let salt = core::slice::from_raw_parts(salt_ptr, salt_len);
+  let secret = optional_slice(secret_ptr, secret_len);
+  let data = optional_slice(data_ptr, data_len);
+
+  status(context::get(handle).and_then(|context| {
+    let params = context.params.with_data(data)?;
+    let digest = try_hash(password, salt, secret, params, &mut context.blocks)?;
+    write_digest(digest, output_ptr)
+  }))
+}
+
+/// Like [`verify`], using the memory of a context. Digests needing more memory
+/// than the context holds grow it.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe fn context_verify(
+  handle: u32,
+
+  digest_ptr: *const u8,
+  digest_len: usize,
+
+  password_ptr: *const u8,
+  password_len: usize,
+
+  secret_ptr: *const u8,
+  secret_len: usize,
+
+  matches: *mut u32,
+) -> u32 {
+  let digest = core::slice::from_raw_parts(digest_ptr, digest_len);
+  let password = core::slice::from_raw_parts(password_ptr, password_len);
+  let secret = optional_slice(secret_ptr, secret_len);
+
+  status(context::get(handle).and_then(|context| {
+    let phc = parse_digest(digest)?;
+    let password_valid =
+      verify_phc(&phc, password, secret, &mut context.blocks)?;
+    *matches = password_valid as u32;
+    Ok(())
+  }))
+}
+
+/// Frees a context and its memory blocks.
+#[no_mangle]
+pub fn context_free(handle: u32) -> u32 {
+  status(context::remove(handle))
+}
+
+/// Starts computing a digest like [`hash_with_params`] without filling any
+/// Argon2 memory yet, and writes the handle of the job to `job_ptr`. The job
+/// is advanced with [`hash_step`] and completed with [`hash_finish`] or
+/// [`hash_finish_raw`]. The password and secret are not kept, so the host may
+/// free them once this returns.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe fn hash_begin(
+  password_ptr: *const u8,
+  password_len: usize,
+
+  salt_ptr: *const u8,
+  salt_len: usize,
+
+  secret_ptr: *const u8,
+  secret_len: usize,
+
+  data_ptr: *const u8,
+  data_len: usize,
+
+  algorithm: [u8; 4],
+  version: u32,
+  m_cost: u32,
+  t_cost: u32,
+  p_cost: u32,
+  output_len: usize,
+
+  job_ptr: *mut u32,
+) -> u32 {
+  let password = core::slice::from_raw_parts(password_ptr, password_len);
+  let salt = core::slice::from_raw_parts(salt_ptr, salt_len);
+  let secret = optional_slice(secret_ptr, secret_len);
+  let data = optional_slice(data_ptr, data_len);
+
+  let params =
+    AllParams::new(algorithm, version, m_cost, t_cost, p_cost, output_len)
+      .and_then(|params| params.with_data(data));
+
+  status(params.and_then(|params| {
+    let hasher = params.hasher(secret)?;
+    hasher.check(password, salt)?;
+    let mut blocks = Matrix::new();
+    blocks.grow(hasher.params.block_count())?;
+    let fill = hasher.start(password, salt, &mut blocks)?;
+
+    *job_ptr = job::insert(HashJob {
+      params,
+      salt: salt.to_vec(),
+      fill,
+      blocks,
+    });
+    Ok(())
+  }))
+}
+
+/// Computes at most `budget` more Argon2 memory blocks of a job, then writes
+/// `1` to `done` if all of them have been computed and `0` otherwise. Unlike
+/// the one-shot exports, this neither reports progress nor polls for
+/// cancellation; the host decides between steps whether to go on.
+#[no_mangle]
+pub unsafe fn hash_step(handle: u32, budget: usize, done: *mut u32) -> u32 {
+  status(job::get(handle).map(|job| {
+    let mut budget = budget;
+    while budget > 0 && !job.fill.is_done() {
+      budget -= job.fill.step(&mut job.blocks, budget);
+    }
+
+    *done = job.fill.is_done() as u32;
+  }))
+}
+
+/// Derives the tag of a job whose blocks have all been computed, then frees
+/// the job. A job that isn't done fails with [`Error::Unfinished`] and is kept.
+fn finish_job(handle: u32, output: &mut [u8]) -> Result<HashJob> {
+  let job = job::get(handle)?;
+  if !job.fill.is_done() {
+    return Err(Error::Unfinished.with("Hash job is not finished"));
+  }
+
+  job
+    .fill
+    .finish(&mut job.blocks, output)
+    .context("Failed to hash password")?;
+
+  job::remove(handle)
+}
+
+/// Completes a job, writing its PHC digest to `output_ptr` like
+/// [`hash_with_params`].
+#[no_mangle]
+pub unsafe fn hash_finish(handle: u32, output_ptr: *mut *mut u8) -> u32 {
+  let output_len = job::get(handle).map(|job| job.params.output_len);
+
+  status(output_len.and_then(|output_len| {
+    let mut hash = vec![0; output_len];
+    let job = finish_job(handle, &mut hash)?;
+    let digest = encode_digest(job.params, &job.salt, hash)?;
+    write_digest(digest, output_ptr)
+  }))
+}
+
+/// Completes a job, writing its raw tag to `output_ptr` like [`hash_raw`].
+/// `output_len` must be the length the job was started with.
+#[no_mangle]
+pub unsafe fn hash_finish_raw(
+  handle: u32,
+  output_ptr: *mut u8,
+  output_len: usize,
+) -> u32 {
+  let output = core::slice::from_raw_parts_mut(output_ptr, output_len);
+
+  status(finish_job(handle, output).map(drop))
+}
+
+/// Frees a job without completing it, wiping its memory blocks.
+#[no_mangle]
+pub fn hash_abort(handle: u32) -> u32 {
+  status(job::remove(handle).map(drop))
 }
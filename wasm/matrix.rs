@@ -0,0 +1,238 @@
+//! Storage of the Argon2 block matrix.
+//!
+//! Linear memory never shrinks, so by default one hash with a large memory
+//! cost inflates the instance for good. The `scratch` build instead keeps the
+//! blocks in memory the host provides through imports, one buffer per matrix,
+//! which the host drops once the module frees it. The buffers still count in
+//! `memory_stats` and against the memory limit. Rust can't address a second
+//! wasm memory directly, so blocks are copied in and out one at a time, which
+//! makes hashing slower.
+
+#[cfg(not(feature = "scratch"))]
+pub use linear::{Blocks, Matrix};
+#[cfg(feature = "scratch")]
+pub use scratch::{Blocks, Matrix};
+
+#[cfg(all(feature = "scratch", feature = "threads"))]
+compile_error!("the `scratch` and `threads` features can't be combined");
+
+use crate::error::{Error, Failure};
+
+fn out_of_memory(bytes: usize) -> Failure {
+  let message = alloc::format!("Failed to allocate {bytes} bytes of memory");
+  Error::OutOfMemory.with(message)
+}
+
+#[cfg(not(feature = "scratch"))]
+mod linear {
+  use crate::engine::Block;
+  use crate::error::Result;
+  use alloc::vec::Vec;
+  use zeroize::Zeroize;
+
+  /// Blocks in linear memory.
+  pub struct Matrix {
+    blocks: Vec<Block>,
+  }
+
+  impl Matrix {
+    pub const fn new() -> Self {
+      Matrix { blocks: Vec::new() }
+    }
+
+    pub fn len(&self) -> usize {
+      self.blocks.len()
+    }
+
+    /// Grows the matrix to at least `count` blocks, failing with
+    /// [`Error::OutOfMemory`](crate::error::Error::OutOfMemory) rather than
+    /// trapping when they can't be allocated.
+    pub fn grow(&mut self, count: usize) -> Result<()> {
+      let Some(additional) = count.checked_sub(self.blocks.len()) else {
+        return Ok(());
+      };
+
+      let bytes = additional.saturating_mul(core::mem::size_of::<Block>());
+      crate::allocator::reserve(bytes)?;
+      self
+        .blocks
+        .try_reserve_exact(additional)
+        .map_err(|_| super::out_of_memory(bytes))?;
+
+      self.blocks.resize(count, Block::default());
+      Ok(())
+    }
+
+    /// Zeroes every block, since they are derived from the password.
+    pub fn wipe(&mut self) {
+      self.blocks.iter_mut().zeroize();
+    }
+
+    pub fn blocks(&mut self) -> Blocks {
+      Blocks {
+        ptr: self.blocks.as_mut_ptr(),
+        len: self.blocks.len(),
+      }
+    }
+  }
+
+  impl Drop for Matrix {
+    fn drop(&mut self) {
+      self.wipe();
+    }
+  }
+
+  /// Access to the blocks of a [`Matrix`] that can be shared between the
+  /// threads computing its lanes.
+  #[derive(Clone, Copy)]
+  pub struct Blocks {
+    ptr: *mut Block,
+    len: usize,
+  }
+
+  impl Blocks {
+    pub fn len(self) -> usize {
+      self.len
+    }
+
+    /// Returns block `index`. The buffer is only used by the `scratch` build.
+    ///
+    /// # Safety
+    ///
+    /// `index` must be in bounds and the block not written meanwhile.
+    pub unsafe fn get(self, index: usize, _buffer: &mut Block) -> &Block {
+      &*self.ptr.add(index)
+    }
+
+    /// Overwrites block `index`.
+    ///
+    /// # Safety
+    ///
+    /// `index` must be in bounds and the block not accessed meanwhile.
+    pub unsafe fn set(self, index: usize, block: &Block) {
+      *self.ptr.add(index) = *block;
+    }
+  }
+}
+
+#[cfg(feature = "scratch")]
+mod scratch {
+  use crate::engine::Block;
+  use crate::error::Result;
+
+  extern "C" {
+    /// Creates a scratch buffer of `count` zeroed blocks and returns its
+    /// non-zero id, or zero if the host can't provide one.
+    fn scratch_new(count: usize) -> u32;
+    /// Copies block `index` of a scratch buffer to the 1 KiB at `block`.
+    fn scratch_read(id: u32, index: usize, block: *mut u8);
+    /// Copies the 1 KiB at `block` to block `index` of a scratch buffer.
+    fn scratch_write(id: u32, index: usize, block: *const u8);
+    /// Zeroes every block of a scratch buffer.
+    fn scratch_wipe(id: u32);
+    /// Zeroes a scratch buffer and lets the host drop it.
+    fn scratch_free(id: u32);
+  }
+
+  /// Blocks in a scratch buffer of the host.
+  pub struct Matrix {
+    id: u32,
+    len: usize,
+  }
+
+  impl Matrix {
+    pub const fn new() -> Self {
+      Matrix { id: 0, len: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+      self.len
+    }
+
+    /// Grows the matrix to at least `count` blocks, failing with
+    /// [`Error::OutOfMemory`](crate::error::Error::OutOfMemory) when the host
+    /// can't provide them. The scratch buffer is replaced, so the blocks are
+    /// zeroed. They count against the memory limit like linear memory does.
+    pub fn grow(&mut self, count: usize) -> Result<()> {
+      if count <= self.len {
+        return Ok(());
+      }
+
+      self.free();
+      let bytes = count.saturating_mul(core::mem::size_of::<Block>());
+      crate::allocator::claim(bytes)?;
+
+      let id = unsafe { scratch_new(count) };
+      if id == 0 {
+        crate::allocator::release(bytes);
+        return Err(super::out_of_memory(bytes));
+      }
+      self.id = id;
+      self.len = count;
+      Ok(())
+    }
+
+    /// Zeroes every block, since they are derived from the password. The
+    /// scratch buffer is kept for the next hash.
+    pub fn wipe(&mut self) {
+      if self.id != 0 {
+        unsafe { scratch_wipe(self.id) };
+      }
+    }
+
+    /// Zeroes and releases the scratch buffer.
+    fn free(&mut self) {
+      let id = core::mem::take(&mut self.id);
+      let len = core::mem::take(&mut self.len);
+      if id != 0 {
+        unsafe { scratch_free(id) };
+        crate::allocator::release(len * core::mem::size_of::<Block>());
+      }
+    }
+
+    pub fn blocks(&mut self) -> Blocks {
+      Blocks {
+        id: self.id,
+        len: self.len,
+      }
+    }
+  }
+
+  impl Drop for Matrix {
+    fn drop(&mut self) {
+      self.free();
+    }
+  }
+
+  /// Access to the blocks of a [`Matrix`].
+  #[derive(Clone, Copy)]
+  pub struct Blocks {
+    id: u32,
+    len: usize,
+  }
+
+  impl Blocks {
+    pub fn len(self) -> usize {
+      self.len
+    }
+
+    /// Copies block `index` into `buffer` and returns it.
+    ///
+    /// # Safety
+    ///
+    /// `index` must be in bounds.
+    pub unsafe fn get(self, index: usize, buffer: &mut Block) -> &Block {
+      scratch_read(self.id, index, (buffer as *mut Block).cast());
+      buffer
+    }
+
+    /// Overwrites block `index`.
+    ///
+    /// # Safety
+    ///
+    /// `index` must be in bounds.
+    pub unsafe fn set(self, index: usize, block: &Block) {
+      scratch_write(self.id, index, (block as *const Block).cast());
+    }
+  }
+}
@@ -1,45 +1,297 @@
-import { source } from "./wasm.js";
-export default async (_WebAssembly: typeof WebAssembly) => {
-  const wasmSource = await source(_WebAssembly);
+import { simdSource, source } from "./wasm.js";
+import { setupThread } from "./thread.ts";
 
-  const { instance } = await _WebAssembly.instantiate(wasmSource, {
+/**
+ * How to instantiate the module. By default the single-threaded build in
+ * `wasm.js` is instantiated with memory of its own.
+ */
+export type WasmOptions = {
+  /** A compiled build to instantiate instead, such as the threaded one. */
+  module?: WebAssembly.Module;
+  /**
+   * Whether to use the build with SIMD instructions, which computes identical
+   * hashes faster, rather than the scalar one. By default it is used if the
+   * runtime supports SIMD. Ignored when {@link WasmOptions.module} is given.
+   */
+  simd?: boolean;
+  /**
+   * Shared memory to import, required by the `atomics` and `threads` builds.
+   * The instance is given a stack of its own in it, see `wasm/thread.ts`.
+   */
+  memory?: WebAssembly.Memory;
+  /**
+   * Workers to start computing Argon2 lanes in parallel, for the threaded
+   * build only. See `wasm/lane_worker.ts`.
+   */
+  laneWorkers?: number;
+};
+
+/**
+ * The smallest module using a SIMD instruction, which only validates where
+ * SIMD is supported.
+ */
+// deno-fmt-ignore
+const SIMD_PROBE = new Uint8Array([
+  0, 97, 115, 109, 1, 0, 0, 0, 1, 5, 1, 96, 0, 1, 123, 3, 2, 1, 0, 10, 10, 1, 8,
+  0, 65, 0, 253, 15, 253, 98, 11,
+]);
+
+/** Size in bytes of an Argon2 memory block. */
+const BLOCK_SIZE = 1024;
+
+export default async (_WebAssembly: typeof WebAssembly, options: WasmOptions = {}) => {
+  const simd = options.simd ?? _WebAssembly.validate(SIMD_PROBE);
+
+  // Set by the caller around calls that should observe them
+  const callbacks: {
+    shouldCancel?: () => boolean;
+    progress?: (pass: number, slice: number, lane: number, fraction: number) => void;
+  } = {};
+
+  // Scratch buffers holding the Argon2 blocks of the `scratch` build, by id.
+  // Each is zeroed after every hash and dropped once the module frees it,
+  // unlike linear memory.
+  const scratch = new Map<number, Uint8Array>();
+  let nextScratchId = 1;
+  // A view of all of memory, replaced once growing detaches its buffer, so
+  // copying a block only takes a view of the source
+  let bytes = new Uint8Array(0);
+  const memoryBytes = () =>
+    bytes.buffer === memory.buffer ? bytes : (bytes = new Uint8Array(memory.buffer));
+
+  const imports = {
     env: {
-      panic: (ptr: number, len: number) => {
+      ...(options.memory && { memory: options.memory }),
+      should_cancel: () => (callbacks.shouldCancel?.() ? 1 : 0),
+      progress: (pass: number, slice: number, lane: number, fraction: number) =>
+        callbacks.progress?.(pass, slice, lane, fraction),
+      scratch_new: (count: number | bigint) => {
+        let buffer;
+        try {
+          buffer = new Uint8Array(Number(count) * BLOCK_SIZE);
+        } catch {
+          return 0;
+        }
+        scratch.set(nextScratchId, buffer);
+        return nextScratchId++;
+      },
+      scratch_read: (id: number, index: number | bigint, ptr: number | bigint) => {
+        const start = Number(index) * BLOCK_SIZE;
+        memoryBytes().set(scratch.get(id)!.subarray(start, start + BLOCK_SIZE), Number(ptr));
+      },
+      scratch_write: (id: number, index: number | bigint, ptr: number | bigint) => {
+        const start = Number(ptr);
+        scratch.get(id)!.set(memoryBytes().subarray(start, start + BLOCK_SIZE), Number(index) * BLOCK_SIZE);
+      },
+      scratch_wipe: (id: number) => {
+        scratch.get(id)!.fill(0);
+      },
+      scratch_free: (id: number) => {
+        scratch.get(id)!.fill(0);
+        scratch.delete(id);
+      },
+      panic: (ptr: number | bigint, len: number | bigint) => {
+        // The message is in static memory owned by the module, copied out
+        // since shared memory can't be decoded directly
         const msg = new TextDecoder().decode(
-          new Uint8Array(memory.buffer, ptr, len),
+          new Uint8Array(memory.buffer, Number(ptr), Number(len)).slice(),
         );
-        dealloc(ptr, len);
         throw new Error(msg);
       },
     },
-  });
+  };
+
+  const instance = options.module
+    ? await _WebAssembly.instantiate(options.module, imports)
+    : (await _WebAssembly.instantiate(await (simd ? simdSource : source)(_WebAssembly), imports)).instance;
+
+  // The memory64 build passes pointers and sizes as i64, which JS sees as
+  // BigInt. So is its stack pointer, telling the builds apart.
+  const memory64 = typeof (instance.exports.__stack_pointer as
+    | typeof _WebAssembly.Global.prototype
+    | undefined)?.value === "bigint";
+  /** Size in bytes of the pointers and sizes the module writes to memory. */
+  const pointerSize = memory64 ? 8 : 4;
 
-  const memory = instance.exports.memory as typeof _WebAssembly.Memory.prototype;
-  const alloc = instance.exports.alloc as (size: number) => number;
-  const dealloc = instance.exports.dealloc as (
+  /**
+   * Binds an export taking `usize` arguments where `signature` has a `p`, one
+   * letter per argument, and 32-bit integers elsewhere. In the memory64 build
+   * these arguments are converted to BigInt, and a returned pointer back.
+   */
+  const bind = (name: string, signature = "") => {
+    const exported = instance.exports[name] as (...args: unknown[]) => unknown;
+    if (!memory64) {
+      return exported;
+    }
+    return (...args: number[]) => {
+      const result = exported(
+        ...args.map((arg, i) => (signature[i] === "p" ? BigInt(arg) : arg)),
+      );
+      return typeof result === "bigint" ? Number(result) : result;
+    };
+  };
+
+  const memory = options.memory ??
+    instance.exports.memory as typeof _WebAssembly.Memory.prototype;
+  if (options.memory) {
+    setupThread(instance.exports, memory);
+  }
+  // Missing from builds predating ABI versions
+  const abiVersion = instance.exports.abi_version
+    ? bind("abi_version") as () => number
+    : () => 0;
+  const capabilities = bind("capabilities") as () => number;
+  // Only exported by the `threads` build
+  const stopLaneWorkers = instance.exports.stop_lane_workers
+    ? bind("stop_lane_workers") as () => void
+    : () => {};
+
+  const alloc = bind("alloc", "p") as (size: number) => number;
+  const dealloc = bind("dealloc", "pp") as (
+    ptr: number,
+    size: number,
+  ) => void;
+  const deallocZeroize = bind("dealloc_zeroize", "pp") as (
     ptr: number,
     size: number,
   ) => void;
 
-  const setupParams = instance.exports.setup_params as (
+  const lastError = bind("last_error", "pp") as (
+    outputPtr: number,
+    outputLen: number,
+  ) => void;
+
+  const setupParams = bind("setup_params") as (
     algorithm: number,
     version: number,
     mCost: number,
     tCost: number,
     pCost: number,
-  ) => void;
+  ) => number;
+
+  const hash = bind("hash", "ppppppp") as (
+    passwordPtr: number,
+    passwordLen: number,
+    saltPtr: number,
+    saltLen: number,
+    secretPtr: number,
+    secretLen: number,
+    outputLocPtr: number,
+  ) => number;
 
-  const hash = instance.exports.hash as (
+  const hashWithParams = bind("hash_with_params", "ppppppppiiiiipp") as (
     passwordPtr: number,
     passwordLen: number,
     saltPtr: number,
     saltLen: number,
     secretPtr: number,
     secretLen: number,
+    dataPtr: number,
+    dataLen: number,
+    algorithm: number,
+    version: number,
+    mCost: number,
+    tCost: number,
+    pCost: number,
+    outputLen: number,
+    outputLocPtr: number,
+  ) => number;
+
+  const hashRaw = bind("hash_raw", "ppppppppiiiiipp") as (
+    passwordPtr: number,
+    passwordLen: number,
+    saltPtr: number,
+    saltLen: number,
+    secretPtr: number,
+    secretLen: number,
+    dataPtr: number,
+    dataLen: number,
+    algorithm: number,
+    version: number,
+    mCost: number,
+    tCost: number,
+    pCost: number,
+    outputPtr: number,
+    outputLen: number,
+  ) => number;
+
+  const verify = bind("verify", "ppppppp") as (
+    digestPtr: number,
+    digestLen: number,
+    passwordPtr: number,
+    passwordLen: number,
+    secretPtr: number,
+    secretLen: number,
+    matches: number,
+  ) => number;
+
+  const needsRehash = bind("needs_rehash", "ppiiiiippp") as (
+    digestPtr: number,
+    digestLen: number,
+    algorithm: number,
+    version: number,
+    mCost: number,
+    tCost: number,
+    pCost: number,
+    outputLen: number,
+    saltLen: number,
+    outdated: number,
+  ) => number;
+
+  const verifyAndUpgrade = bind("verify_and_upgrade", "ppppppppppiiiiippp") as (
+    digestPtr: number,
+    digestLen: number,
+    passwordPtr: number,
+    passwordLen: number,
+    secretPtr: number,
+    secretLen: number,
+    saltPtr: number,
+    saltLen: number,
+    dataPtr: number,
+    dataLen: number,
+    algorithm: number,
+    version: number,
+    mCost: number,
+    tCost: number,
+    pCost: number,
+    outputLen: number,
+    matches: number,
     outputLocPtr: number,
+  ) => number;
+
+  const setVerifyPolicy = bind("set_verify_policy") as (
+    maxMCost: number,
+    maxTCost: number,
+    maxPCost: number,
+    algorithms: number,
+    versions: number,
   ) => void;
 
-  const verify = instance.exports.verify as (
+  const contextNew = bind("context_new", "iiiiipp") as (
+    algorithm: number,
+    version: number,
+    mCost: number,
+    tCost: number,
+    pCost: number,
+    outputLen: number,
+    handlePtr: number,
+  ) => number;
+
+  const contextHash = bind("context_hash", "ippppppppp") as (
+    handle: number,
+    passwordPtr: number,
+    passwordLen: number,
+    saltPtr: number,
+    saltLen: number,
+    secretPtr: number,
+    secretLen: number,
+    dataPtr: number,
+    dataLen: number,
+    outputLocPtr: number,
+  ) => number;
+
+  const contextVerify = bind("context_verify", "ippppppp") as (
+    handle: number,
     digestPtr: number,
     digestLen: number,
     passwordPtr: number,
@@ -47,7 +299,118 @@ export default async (_WebAssembly: typeof WebAssembly) => {
     secretPtr: number,
     secretLen: number,
     matches: number,
+  ) => number;
+
+  const contextFree = bind("context_free") as (
+    handle: number,
+  ) => number;
+
+  const hashBegin = bind("hash_begin", "ppppppppiiiiipp") as (
+    passwordPtr: number,
+    passwordLen: number,
+    saltPtr: number,
+    saltLen: number,
+    secretPtr: number,
+    secretLen: number,
+    dataPtr: number,
+    dataLen: number,
+    algorithm: number,
+    version: number,
+    mCost: number,
+    tCost: number,
+    pCost: number,
+    outputLen: number,
+    jobPtr: number,
+  ) => number;
+
+  const hashStep = bind("hash_step", "ipp") as (
+    job: number,
+    budget: number,
+    donePtr: number,
+  ) => number;
+
+  const hashFinish = bind("hash_finish", "ip") as (
+    job: number,
+    outputLocPtr: number,
+  ) => number;
+
+  const hashFinishRaw = bind("hash_finish_raw", "ipp") as (
+    job: number,
+    outputPtr: number,
+    outputLen: number,
+  ) => number;
+
+  const hashAbort = bind("hash_abort") as (
+    job: number,
+  ) => number;
+
+  const memoryStats = bind("memory_stats") as () => number;
+
+  const setMemoryLimit = bind("set_memory_limit", "p") as (
+    maxBytes: number,
   ) => void;
 
-  return { memory, alloc, dealloc, setupParams, hash, verify };
+  const isPoisoned = bind("is_poisoned") as () => number;
+
+  // A trap unwinds past the frames that would restore the stack pointer, so
+  // it is put back to its initial value along with the module's own state.
+  const stackPointer = instance.exports.__stack_pointer as
+    | typeof _WebAssembly.Global.prototype
+    | undefined;
+  const initialStackPointer = stackPointer?.value;
+  const resetModule = instance.exports.reset as () => void;
+  const reset = () => {
+    if (stackPointer) {
+      stackPointer.value = initialStackPointer;
+    }
+    resetModule();
+    // The module forgets its contexts and jobs without freeing their blocks
+    for (const buffer of scratch.values()) {
+      buffer.fill(0);
+    }
+    scratch.clear();
+  };
+
+  const laneWorkers = Array.from({ length: options.laneWorkers ?? 0 }, () => {
+    const worker = new Worker(new URL("./lane_worker.ts", import.meta.url), {
+      type: "module",
+    });
+    worker.postMessage({ module: options.module, memory });
+    return worker;
+  });
+
+  return {
+    callbacks,
+    laneWorkers,
+    stopLaneWorkers,
+    memory,
+    pointerSize,
+    abiVersion,
+    capabilities,
+    alloc,
+    dealloc,
+    deallocZeroize,
+    lastError,
+    setupParams,
+    hash,
+    hashWithParams,
+    hashRaw,
+    verify,
+    needsRehash,
+    verifyAndUpgrade,
+    setVerifyPolicy,
+    contextNew,
+    contextHash,
+    contextVerify,
+    contextFree,
+    hashBegin,
+    hashStep,
+    hashFinish,
+    hashFinishRaw,
+    hashAbort,
+    memoryStats,
+    setMemoryLimit,
+    isPoisoned,
+    reset,
+  };
 };
@@ -0,0 +1,213 @@
+//! Argon2 PHC string encoding and decoding.
+//!
+//! This is done here rather than through `password_hash`, whose fixed-size
+//! buffers cap salts at 48 bytes and tags at 64 bytes.
+
+use crate::error::{Error, Failure, Result};
+use alloc::{format, vec::Vec};
+use base64::display::Base64Display;
+use base64::engine::general_purpose::STANDARD_NO_PAD as B64;
+use base64::Engine;
+use core::fmt::{self, Write};
+use zeroize::Zeroize;
+
+/// A parsed (or to be formatted) Argon2 PHC string. The salt and tag are
+/// wiped when it is dropped.
+pub struct Phc {
+  pub algorithm: argon2::Algorithm,
+  pub version: argon2::Version,
+  pub params: argon2::Params,
+  pub salt: Vec<u8>,
+  pub hash: Vec<u8>,
+}
+
+impl Phc {
+  /// Parses `$argon2<type>[$v=<version>]$<params>$<salt>$<hash>`.
+  pub fn parse(digest: &str) -> Result<Self> {
+    let mut fields = digest.split('$');
+    if fields.next() != Some("") {
+      return Err(format_error("expected a leading '$'"));
+    }
+
+    let algorithm = match fields.next() {
+      Some("argon2i") => argon2::Algorithm::Argon2i,
+      Some("argon2d") => argon2::Algorithm::Argon2d,
+      Some("argon2id") => argon2::Algorithm::Argon2id,
+      Some(_) => return Err(Error::InvalidAlgorithm.with("Invalid algorithm")),
+      None => return Err(format_error("missing algorithm")),
+    };
+
+    let mut field = fields.next();
+
+    let version = match field.and_then(|field| field.strip_prefix("v=")) {
+      Some(version) => {
+        field = fields.next();
+        match decimal(version)? {
+          0x10 => argon2::Version::V0x10,
+          0x13 => argon2::Version::V0x13,
+          _ => {
+            let message = format!("Invalid {algorithm} version");
+            return Err(Error::InvalidVersion.with(message));
+          }
+        }
+      }
+      None => argon2::Version::default(),
+    };
+
+    let mut builder = argon2::ParamsBuilder::new();
+    if let Some(params) = field.filter(|field| field.contains('=')) {
+      field = fields.next();
+      parse_params(params, &mut builder)?;
+    }
+
+    let salt = field.ok_or_else(|| format_error("missing salt"))?;
+    let salt = B64.decode(salt).map_err(format_error)?;
+
+    let hash = fields.next().ok_or_else(|| format_error("missing hash"))?;
+    let hash = B64.decode(hash).map_err(format_error)?;
+
+    if fields.next().is_some() {
+      return Err(format_error("trailing data"));
+    }
+
+    let params = builder
+      .output_len(hash.len())
+      .build()
+      .map_err(|error| params_error(format_args!("{error}")))?;
+
+    Ok(Phc {
+      algorithm,
+      version,
+      params,
+      salt,
+      hash,
+    })
+  }
+
+  /// Length of the formatted PHC string.
+  pub fn encoded_len(&self) -> usize {
+    let mut counter = Counter(0);
+    let _ = write!(counter, "{self}");
+    counter.0
+  }
+
+  /// Formats the PHC string into `output`, which must be
+  /// [`Phc::encoded_len`] bytes long, without copying it through buffers
+  /// that would be left unwiped.
+  pub fn encode_into(&self, output: &mut [u8]) {
+    let mut writer = SliceWriter(output);
+    let written = write!(writer, "{self}");
+    debug_assert!(written.is_ok() && writer.0.is_empty());
+  }
+}
+
+struct Counter(usize);
+
+impl Write for Counter {
+  fn write_str(&mut self, s: &str) -> fmt::Result {
+    self.0 += s.len();
+    Ok(())
+  }
+}
+
+struct SliceWriter<'a>(&'a mut [u8]);
+
+impl Write for SliceWriter<'_> {
+  fn write_str(&mut self, s: &str) -> fmt::Result {
+    if s.len() > self.0.len() {
+      return Err(fmt::Error);
+    }
+    let (head, tail) = core::mem::take(&mut self.0).split_at_mut(s.len());
+    head.copy_from_slice(s.as_bytes());
+    self.0 = tail;
+    Ok(())
+  }
+}
+
+impl Drop for Phc {
+  fn drop(&mut self) {
+    self.salt.zeroize();
+    self.hash.zeroize();
+  }
+}
+
+impl fmt::Display for Phc {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let params = &self.params;
+
+    write!(f, "${}", self.algorithm)?;
+    write!(f, "$v={}", u32::from(self.version))?;
+    write!(f, "$m={},t={}", params.m_cost(), params.t_cost())?;
+    write!(f, ",p={}", params.p_cost())?;
+    if !params.keyid().is_empty() {
+      write!(f, ",keyid={}", Base64Display::new(params.keyid(), &B64))?;
+    }
+    if !params.data().is_empty() {
+      write!(f, ",data={}", Base64Display::new(params.data(), &B64))?;
+    }
+    write!(f, "${}", Base64Display::new(&self.salt, &B64))?;
+    write!(f, "${}", Base64Display::new(&self.hash, &B64))
+  }
+}
+
+fn parse_params(
+  params: &str,
+  builder: &mut argon2::ParamsBuilder,
+) -> Result<()> {
+  let mut seen = Vec::new();
+
+  for param in params.split(',') {
+    let (name, value) = param
+      .split_once('=')
+      .ok_or_else(|| format_error("expected <name>=<value>"))?;
+
+    if seen.contains(&name) {
+      return Err(params_error(format_args!("duplicate parameter {name}")));
+    }
+    seen.push(name);
+
+    match name {
+      "m" => builder.m_cost(decimal(value)?),
+      "t" => builder.t_cost(decimal(value)?),
+      "p" => builder.p_cost(decimal(value)?),
+      "keyid" => builder.keyid(
+        argon2::KeyId::from_b64(value)
+          .map_err(|error| params_error(format_args!("keyid {error}")))?,
+      ),
+      "data" => builder.data(
+        argon2::AssociatedData::from_b64(value)
+          .map_err(|error| params_error(format_args!("data {error}")))?,
+      ),
+      _ => return Err(params_error(format_args!("unknown parameter {name}"))),
+    };
+  }
+
+  Ok(())
+}
+
+/// Parses a PHC decimal: ASCII digits only, without leading zeros.
+fn decimal(value: &str) -> Result<u32> {
+  let canonical = !value.is_empty()
+    && value.bytes().all(|byte| byte.is_ascii_digit())
+    && (value == "0" || !value.starts_with('0'));
+
+  match value.parse() {
+    Ok(value) if canonical => Ok(value),
+    _ => Err(format_error(format_args!("invalid decimal {value:?}"))),
+  }
+}
+
+fn format_error(reason: impl fmt::Display) -> Failure {
+  Error::MalformedDigest.with(format_args!("Invalid digest format: {reason}"))
+}
+
+fn params_error(reason: impl fmt::Display) -> Failure {
+  Error::MalformedDigest
+    .with(format_args!("Invalid digest parameters: {reason}"))
+}
+
+/// Compares two tags without branching on their contents.
+pub fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+  let diff = a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b));
+  a.len() == b.len() && core::hint::black_box(diff) == 0
+}
@@ -0,0 +1,68 @@
+use crate::error::{Error, Result};
+use crate::phc::Phc;
+
+/// Limits on the digests `verify` is willing to compute.
+///
+/// Digests may come from less trusted sources, and their parameters decide how
+/// much memory and time verification takes, so they are checked against this
+/// before any Argon2 memory is allocated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VerifyPolicy {
+  pub max_m_cost: u32,
+  pub max_t_cost: u32,
+  pub max_p_cost: u32,
+  /// Bit `n` allows the algorithm with discriminant `n` (see
+  /// [`argon2::Algorithm`]): 1 = Argon2d, 2 = Argon2i, 4 = Argon2id.
+  pub algorithms: u32,
+  /// Bit 0 allows version 0x10, bit 1 allows version 0x13.
+  pub versions: u32,
+}
+
+impl VerifyPolicy {
+  /// Accepts every digest, matching the behaviour before policies existed.
+  pub const UNRESTRICTED: VerifyPolicy = VerifyPolicy {
+    max_m_cost: u32::MAX,
+    max_t_cost: u32::MAX,
+    max_p_cost: u32::MAX,
+    algorithms: 0b111,
+    versions: 0b11,
+  };
+
+  pub fn check(&self, phc: &Phc) -> Result<()> {
+    let params = &phc.params;
+
+    let version_bit = match phc.version {
+      argon2::Version::V0x10 => 0b01,
+      argon2::Version::V0x13 => 0b10,
+    };
+
+    let violation = if self.algorithms & (1 << phc.algorithm as u32) == 0 {
+      alloc::format!("algorithm {} is not allowed", phc.algorithm)
+    } else if self.versions & version_bit == 0 {
+      let version = u32::from(phc.version);
+      alloc::format!("version {version:#x} is not allowed")
+    } else if params.m_cost() > self.max_m_cost {
+      alloc::format!("m={} exceeds {}", params.m_cost(), self.max_m_cost)
+    } else if params.t_cost() > self.max_t_cost {
+      alloc::format!("t={} exceeds {}", params.t_cost(), self.max_t_cost)
+    } else if params.p_cost() > self.max_p_cost {
+      alloc::format!("p={} exceeds {}", params.p_cost(), self.max_p_cost)
+    } else {
+      return Ok(());
+    };
+
+    let message = alloc::format!("Digest rejected by policy: {violation}");
+    Err(Error::PolicyViolation.with(message))
+  }
+}
+
+#[cfg_attr(feature = "atomics", thread_local)]
+static mut VERIFY_POLICY: VerifyPolicy = VerifyPolicy::UNRESTRICTED;
+
+pub fn verify_policy() -> VerifyPolicy {
+  unsafe { VERIFY_POLICY }
+}
+
+pub fn set_verify_policy(policy: VerifyPolicy) {
+  unsafe { VERIFY_POLICY = policy };
+}
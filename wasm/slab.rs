@@ -0,0 +1,57 @@
+use crate::error::{Error, Result};
+use alloc::vec::Vec;
+
+/// Values addressed by `u32` handles, as stored by the host. A handle is the
+/// index of its slot plus one, so that `0` is never a handle.
+pub struct Slab<T> {
+  name: &'static str,
+  slots: Vec<Option<T>>,
+}
+
+impl<T> Slab<T> {
+  /// Creates an empty slab whose invalid handles are reported as invalid
+  /// `name` handles.
+  pub const fn new(name: &'static str) -> Self {
+    Slab {
+      name,
+      slots: Vec::new(),
+    }
+  }
+
+  /// Stores a value, reusing a freed slot if there is one.
+  pub fn insert(&mut self, value: T) -> u32 {
+    let index = match self.slots.iter().position(Option::is_none) {
+      Some(index) => index,
+      None => {
+        self.slots.push(None);
+        self.slots.len() - 1
+      }
+    };
+
+    self.slots[index] = Some(value);
+    index as u32 + 1
+  }
+
+  pub fn get(&mut self, handle: u32) -> Result<&mut T> {
+    let index = (handle as usize).wrapping_sub(1);
+    let name = self.name;
+
+    self
+      .slots
+      .get_mut(index)
+      .and_then(Option::as_mut)
+      .ok_or_else(|| {
+        Error::InvalidHandle.with(format_args!("Invalid {name} handle"))
+      })
+  }
+
+  pub fn remove(&mut self, handle: u32) -> Result<T> {
+    self.get(handle)?;
+    Ok(self.slots[handle as usize - 1].take().unwrap())
+  }
+
+  /// Removes every value, returning them to the caller to drop or forget.
+  pub fn take_all(&mut self) -> Vec<Option<T>> {
+    core::mem::take(&mut self.slots)
+  }
+}
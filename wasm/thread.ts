@@ -0,0 +1,36 @@
+// Per-thread setup for the `atomics` and `threads` builds, whose instances
+// share one memory from several threads.
+//
+// Every instance starts out with the stack pointer and thread-local storage
+// of the first one, so instances running at the same time would overwrite
+// each other's stack frames and module state. Before its first call, each
+// instance is given a stack and a thread-local storage block of its own, in
+// pages grown past the heap so that the allocator never hands them out, not
+// even after a reset. The stack grows down towards the storage block.
+
+/** Size of each instance's stack, in 64 KiB pages. */
+const STACK_PAGES = 4;
+
+/**
+ * Creates shared memory with the limits the `atomics` and `threads` builds are
+ * linked with; see `ATOMICS` in scripts/build.ts.
+ */
+export function createSharedMemory(_WebAssembly: typeof WebAssembly = WebAssembly) {
+  return new _WebAssembly.Memory({ initial: 32, maximum: 65536, shared: true });
+}
+
+/**
+ * Gives an instance of a shared-memory build its own stack and thread-local
+ * storage. Must be called once per instance, before any other export.
+ */
+export function setupThread(
+  exports: WebAssembly.Exports,
+  memory: WebAssembly.Memory,
+) {
+  const tlsSize = (exports.__tls_size as WebAssembly.Global).value as number;
+  const pages = STACK_PAGES + Math.ceil(tlsSize / 65536);
+  const base = memory.grow(pages) * 65536; // page aligned, as TLS must be
+
+  (exports.__wasm_init_tls as (tlsBase: number) => void)(base);
+  (exports.__stack_pointer as WebAssembly.Global).value = base + pages * 65536;
+}
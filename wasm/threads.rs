@@ -0,0 +1,127 @@
+//! Lane parallelism for the `threads` build, whose linear memory is shared
+//! with instances of the module running on worker threads.
+//!
+//! Each worker instance calls [`lane_worker`], which waits for the hashing
+//! thread to post a slice and then helps compute its lanes. The hashing thread
+//! computes lanes as well, so without workers hashing is merely sequential.
+//! Waiting blocks the thread, which browsers don't allow on their main thread.
+//!
+//! The workers take one slice at a time. Other threads hashing meanwhile
+//! compute their lanes sequentially, as does every thread once the host has
+//! called [`stop_lane_workers`].
+
+use crate::arch::{memory_atomic_notify, memory_atomic_wait32};
+use crate::engine::Slice;
+use core::ptr;
+use core::sync::atomic::{
+  AtomicBool, AtomicPtr, AtomicU32, AtomicUsize, Ordering::SeqCst,
+};
+
+/// A slice being computed, with the lanes claimed and finished so far.
+struct Posted<'a> {
+  slice: &'a Slice<'a>,
+  next_lane: AtomicUsize,
+  done_lanes: AtomicU32,
+}
+
+/// The slice being computed, or null between slices.
+static POSTED: AtomicPtr<Posted<'static>> = AtomicPtr::new(ptr::null_mut());
+/// Incremented for each slice posted. Idle lane workers wait for it to change.
+static GENERATION: AtomicU32 = AtomicU32::new(0);
+/// Lane workers that may be looking at the posted slice.
+static ACTIVE: AtomicU32 = AtomicU32::new(0);
+/// Set once the lane workers are terminated. One terminated while counted in
+/// [`ACTIVE`] never uncounts itself, so slices are no longer posted.
+static STOPPED: AtomicBool = AtomicBool::new(false);
+
+/// Computes the lanes of a slice together with whichever lane workers are
+/// idle, returning once every lane is done and no worker refers to the slice.
+pub fn fill_lanes(slice: &Slice) {
+  let posted = Posted {
+    slice,
+    next_lane: AtomicUsize::new(0),
+    done_lanes: AtomicU32::new(0),
+  };
+  let posted_ptr = ptr::addr_of!(posted).cast_mut().cast();
+
+  let null = ptr::null_mut();
+  if slice.lanes() == 1
+    || STOPPED.load(SeqCst)
+    || POSTED
+      .compare_exchange(null, posted_ptr, SeqCst, SeqCst)
+      .is_err()
+  {
+    return posted.claim_lanes();
+  }
+
+  GENERATION.fetch_add(1, SeqCst);
+  unsafe { memory_atomic_notify(GENERATION.as_ptr().cast(), u32::MAX) };
+
+  posted.claim_lanes();
+  let lanes = slice.lanes() as u32;
+  wait_until(&posted.done_lanes, |done| done == lanes);
+
+  // A worker that loads the pointer after this sees null, and one that
+  // loaded it before is counted as active.
+  POSTED.store(ptr::null_mut(), SeqCst);
+  wait_until(&ACTIVE, |active| active == 0);
+}
+
+impl Posted<'_> {
+  fn claim_lanes(&self) {
+    let lanes = self.slice.lanes();
+
+    loop {
+      let lane = self.next_lane.fetch_add(1, SeqCst);
+      if lane >= lanes {
+        return;
+      }
+
+      // SAFETY: the counter hands out each lane once
+      unsafe { self.slice.fill_lane(lane) };
+
+      if self.done_lanes.fetch_add(1, SeqCst) + 1 == lanes as u32 {
+        unsafe { memory_atomic_notify(self.done_lanes.as_ptr().cast(), 1) };
+      }
+    }
+  }
+}
+
+/// Blocks until `atomic` holds a value accepted by `done`.
+fn wait_until(atomic: &AtomicU32, done: impl Fn(u32) -> bool) {
+  loop {
+    let value = atomic.load(SeqCst);
+    if done(value) {
+      return;
+    }
+    unsafe { memory_atomic_wait32(atomic.as_ptr().cast(), value as i32, -1) };
+  }
+}
+
+/// Entry point of a lane worker, an instance of the module on another thread
+/// sharing the hashing thread's memory. Never returns; the host terminates
+/// the worker instead. The worker's stack pointer must be set to a stack of
+/// its own first, see `wasm/lane_worker.ts`.
+#[no_mangle]
+pub unsafe fn lane_worker() {
+  loop {
+    let generation = GENERATION.load(SeqCst);
+
+    ACTIVE.fetch_add(1, SeqCst);
+    if let Some(posted) = POSTED.load(SeqCst).as_ref() {
+      posted.claim_lanes();
+    }
+    if ACTIVE.fetch_sub(1, SeqCst) == 1 {
+      memory_atomic_notify(ACTIVE.as_ptr().cast(), 1);
+    }
+
+    memory_atomic_wait32(GENERATION.as_ptr().cast(), generation as i32, -1);
+  }
+}
+
+/// Stops posting slices to the lane workers, before the host terminates them.
+/// Must not be called while hashing.
+#[no_mangle]
+pub unsafe fn stop_lane_workers() {
+  STOPPED.store(true, SeqCst);
+}